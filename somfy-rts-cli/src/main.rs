@@ -1,23 +1,54 @@
 use std::{
-    ops::{Range, RangeBounds},
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
     process::exit,
 };
 
-use clap::{Arg, ArgAction, Args, Command, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use serde::Deserialize;
 
-use somfy_rts::WireFormat;
+use somfy_rts::{
+    rpc::{self, Call},
+    scene::{Action, Move},
+    transport, WireFormat,
+};
+
+#[path = "../../somfy-rts-mqtt/src/scene.rs"]
+mod scene;
+mod sniff;
+
+use scene::Scene;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Serial port name to operate on. If none is provided, we will attemp to find one.
+    /// Serial port name to operate on. If none is provided, we will go through the socket of
+    /// `somfy-rts-mqtt` when it runs, or attempt to find one.
     #[arg(short, long, value_name = "SERIAL PORT")]
     serial: Option<String>,
 
+    /// Control socket of the process owning the dongle, looked for in `$XDG_RUNTIME_DIR`, then
+    /// in `/run/somfy-rts` by default
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// Record the traffic with the dongle to a capture file in this directory
+    #[arg(long, value_name = "DIR")]
+    record: Option<PathBuf>,
+
+    /// Serial number of the dongle to operate on through the socket, when it drives several
+    #[arg(long, value_name = "SERIAL NUMBER")]
+    dongle: Option<String>,
+
     /// Turn debugging information on
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
 
+    /// Configuration file of the MQTT bridge, to read scenes and groups from.
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -30,13 +61,13 @@ fn validate_range(arg: &str) -> Result<Box<dyn Iterator<Item = u8>>, String> {
     let start_bound = 1;
     let end_bound = 100;
 
-    let start_bound = (!start.is_empty())
-        .then(|| {
-            start
-                .parse::<u8>()
-                .or(Err(format!("`{start}` isn't a valid bound.")))
-        })
-        .unwrap_or(Ok(start_bound))?;
+    let start_bound = if start.is_empty() {
+        start_bound
+    } else {
+        start
+            .parse::<u8>()
+            .or(Err(format!("`{start}` isn't a valid bound.")))?
+    };
 
     let end_stripped = end.strip_prefix('=').map(str::parse::<u8>);
 
@@ -70,6 +101,69 @@ enum Commands {
     GetAddress(BlindRange),
     SetAddress(BlindRange),
     ResetAddress(BlindRange),
+
+    /// Scenes declared in the configuration file.
+    #[command(subcommand)]
+    Scene(SceneCommand),
+
+    /// Sit between a host and the dongle, forwarding traffic both ways and logging it.
+    Sniff(Sniff),
+}
+
+#[derive(Args)]
+struct Sniff {
+    /// Serial port the host (such as the Enki box) talks to
+    host: String,
+
+    /// Serial port of the dongle
+    dongle: String,
+}
+
+#[derive(Subcommand)]
+enum SceneCommand {
+    /// Run a scene, stopping at the first step the dongle refuses.
+    Run {
+        /// Name of the scene
+        name: String,
+    },
+}
+
+/// Groups and scenes, as declared in the configuration file of the bridge, the rest of which is
+/// ignored.
+#[derive(Deserialize, Default)]
+struct SceneConfig {
+    #[serde(default)]
+    groups: BTreeMap<String, Vec<u8>>,
+
+    #[serde(default)]
+    scenes: BTreeMap<String, Scene>,
+
+    #[serde(default)]
+    dongles: BTreeMap<String, SceneConfig>,
+}
+
+impl SceneConfig {
+    fn load(path: &PathBuf) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read '{}': {}", path.display(), e))?;
+
+        toml::from_str(&content).map_err(|e| format!("Invalid '{}': {}", path.display(), e))
+    }
+
+    /// Resolves scene `name` as the bridge would for dongle `serial`: a dongle section, if
+    /// any, replaces the scenes and groups declared at the top.
+    fn actions(mut self, serial: &str, name: &str) -> Result<Vec<Action>, String> {
+        let config = self.dongles.remove(serial).unwrap_or(self);
+
+        let scene = config
+            .scenes
+            .get(name)
+            .ok_or(format!("No scene named `{name}`."))?;
+
+        scene
+            .actions(&config.groups)
+            .map_err(|e| format!("Scene `{name}` is invalid: {e}"))
+    }
 }
 
 #[derive(Args)]
@@ -84,15 +178,126 @@ struct BlindRange {
     range: String,
 }
 
+/// What a command comes down to, when sent through the socket.
+fn calls(command: Option<&Commands>) -> Vec<Call> {
+    let blinds = |range: &str| {
+        validate_range(range).unwrap_or_else(|e| {
+            println!("Invalid range: {}", e);
+            exit(-1);
+        })
+    };
+
+    use Commands::*;
+    match command {
+        Some(Up(Blind { blind })) => vec![operate(*blind, Move::Up)],
+        Some(Down(Blind { blind })) => vec![operate(*blind, Move::Down)],
+        Some(Stop(Blind { blind })) => vec![operate(*blind, Move::Stop)],
+        Some(My(Blind { blind })) => vec![operate(*blind, Move::My)],
+        Some(Prog(Blind { blind })) => vec![Call::Prog { blind: *blind }],
+
+        Some(GetAddress(BlindRange { range })) => blinds(range)
+            .map(|blind| Call::GetAddress { blind })
+            .collect(),
+
+        Some(ResetAddress(BlindRange { range })) => blinds(range)
+            .map(|blind| Call::ResetAddress { blind })
+            .collect(),
+
+        Some(Scene(SceneCommand::Run { name })) => vec![Call::RunScene { name: name.clone() }],
+
+        _ => Vec::new(),
+    }
+}
+
+fn operate(blind: u8, order: Move) -> Call {
+    Call::Operate { blind, order }
+}
+
+/// Runs the command through the process owning the dongle, if one listens on `socket`.
+/// Returns `false` when none does, for the dongle to be opened directly.
+#[cfg(unix)]
+async fn run_remote(socket: &Path, args: &Cli) -> bool {
+    let Ok(mut client) = rpc::Client::connect(socket).await else {
+        return false;
+    };
+
+    println!("Using dongle through: {}", socket.display());
+
+    if args.record.is_some() {
+        println!("Ignoring --record: only a dongle opened directly can be recorded.");
+    }
+
+    if args.config.is_some() {
+        println!("Ignoring --config: scenes are read from the configuration of the bridge.");
+    }
+
+    for call in std::iter::once(Call::Info).chain(calls(args.command.as_ref())) {
+        let request = rpc::Request {
+            dongle: args.dongle.clone(),
+            call,
+        };
+
+        match client.call(&request).await {
+            Ok(rpc::Answer::Info { serial, firmware }) => {
+                println!("Dongle id:{}, {}", serial, firmware)
+            }
+
+            // Over a range, every slot gets printed, as when opening the dongle directly.
+            Ok(rpc::Answer::Refused { message })
+                if matches!(
+                    request.call,
+                    Call::GetAddress { .. } | Call::ResetAddress { .. }
+                ) =>
+            {
+                println!("Dongle returned error: {}", message)
+            }
+
+            Ok(rpc::Answer::Refused { message } | rpc::Answer::Error { message }) => {
+                println!("Dongle returned error: {}", message);
+                exit(-1);
+            }
+
+            Ok(answer) => println!("{:?}", answer),
+
+            Err(e) => {
+                println!("Lost the control socket: {}", e);
+                exit(-1);
+            }
+        }
+    }
+
+    true
+}
+
+#[cfg(not(unix))]
+async fn run_remote(_: &Path, _: &Cli) -> bool {
+    false
+}
+
 #[tokio::main]
 async fn main() {
     println!("Hello, world!");
 
     let args = Cli::parse();
 
-    let provided_dongle = args.serial.as_deref().map(somfy_rts::new);
+    if let Some(Commands::Sniff(Sniff { host, dongle })) = args.command.as_ref() {
+        return sniff::sniff(host, dongle).await;
+    }
+
+    let sockets = match args.socket.as_ref() {
+        Some(socket) => vec![socket.clone()],
+        None => rpc::default_sockets(),
+    };
 
-    let somfy_dongle = match provided_dongle {
+    if args.serial.is_none() {
+        for socket in sockets.iter() {
+            if run_remote(socket, &args).await {
+                return;
+            }
+        }
+    }
+
+    let port = match args.serial {
         None => {
             println!("No dongle was provided.");
             let dongles = somfy_rts::detect();
@@ -106,16 +311,28 @@ async fn main() {
 
             println!("Using dongle at: {}", &dongles[0].port_name);
 
-            somfy_rts::new(&dongles[0].port_name)
+            dongles[0].port_name.clone()
         }
 
-        Some(dongle) => dongle,
-    }
-    .unwrap_or_else(|e| {
+        Some(port) => port,
+    };
+
+    let mut somfy_dongle = somfy_rts::new(&port).unwrap_or_else(|e| {
         println!("Failed to open selected dongle: {}", e);
         exit(-1);
     });
 
+    if let Some(dir) = args.record.as_deref() {
+        let capture = transport::capture_file(dir, &port);
+
+        somfy_dongle = somfy_dongle.record(&capture).unwrap_or_else(|e| {
+            println!("Failed to record to {}: {}", capture.display(), e);
+            exit(-1);
+        });
+
+        println!("Recording traffic to: {}", capture.display());
+    }
+
     let (_, mut dongle_ready) = somfy_dongle
         .initialize(WireFormat::CryptoOff)
         .await
@@ -187,6 +404,27 @@ async fn main() {
                     }
                 }
 
+                Some(Scene(SceneCommand::Run { name })) => {
+                    let config = match args.config.as_ref() {
+                        Some(path) => SceneConfig::load(path),
+                        None => Ok(SceneConfig::default()),
+                    };
+
+                    let actions = config
+                        .and_then(|config| config.actions(&info.id[0], &name))
+                        .unwrap_or_else(|e| {
+                            println!("{}", e);
+                            exit(-1);
+                        });
+
+                    if let Err(e) = dongle_ready.run_scene(&actions).await {
+                        println!("Scene `{}` aborted: {}", name, e);
+                        exit(-1);
+                    }
+
+                    println!("Scene `{}` done.", name);
+                }
+
                 _ => (),
             }
         }
@@ -0,0 +1,171 @@
+use std::{fmt, process::exit, time::Instant};
+
+use serde_json::Value;
+use somfy_rts::frame::{Decoder, Frame};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    select, signal,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Host,
+    Dongle,
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Host => write!(f, "host   -> dongle"),
+            Side::Dongle => write!(f, "dongle -> host  "),
+        }
+    }
+}
+
+/// What a frame is, as far as the protocol is known.
+fn annotate(frame: &Frame) -> String {
+    match frame {
+        Frame::Line(line) if line.starts_with("RTSDONGLE,") => "init answer".to_string(),
+
+        Frame::Line(_) => "line".to_string(),
+
+        Frame::Json(value) => match (value["ACK"].as_str(), value.as_object()) {
+            (Some(ack), _) => format!("answer {}", ack),
+            (None, Some(object)) if object.len() == 1 => {
+                format!("{} command", object.keys().next().unwrap())
+            }
+            _ => "JSON".to_string(),
+        },
+
+        Frame::Raw(bytes) if bytes.starts_with(b"$") => "$ message".to_string(),
+
+        Frame::Raw(_) => "unparsed".to_string(),
+    }
+}
+
+fn describe(frame: &Frame) -> String {
+    match frame {
+        Frame::Line(line) => line.clone(),
+
+        Frame::Json(value) => Value::to_string(value),
+
+        Frame::Raw(bytes) => {
+            let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+            format!("{:?} [{}]", String::from_utf8_lossy(bytes), hex)
+        }
+    }
+}
+
+fn log(started: Instant, side: Side, frame: Frame) {
+    println!(
+        "[{:>9.3}s] {}  {}: {}",
+        started.elapsed().as_secs_f64(),
+        side,
+        annotate(&frame),
+        describe(&frame)
+    );
+}
+
+/// Logs what is held back on either side, when the sniffer stops.
+fn flush(started: Instant, host_decoder: &mut Decoder, dongle_decoder: &mut Decoder) {
+    for (side, decoder) in [(Side::Host, host_decoder), (Side::Dongle, dongle_decoder)] {
+        if let Some(frame) = decoder.flush() {
+            log(started, side, frame);
+        }
+    }
+}
+
+/// Sits between the host (such as the Enki box) at `host` and the dongle at `dongle`,
+/// forwarding everything both ways while logging the frames going by.
+pub async fn sniff(host: &str, dongle: &str) {
+    let open = |path: &str| {
+        somfy_rts::open_port(path).unwrap_or_else(|e| {
+            println!("Failed to open {}: {}", path, e);
+            exit(-1);
+        })
+    };
+
+    let (mut host_rx, mut host_tx) = io::split(open(host));
+    let (mut dongle_rx, mut dongle_tx) = io::split(open(dongle));
+
+    println!("Sniffing between host at {} and dongle at {}", host, dongle);
+
+    let started = Instant::now();
+
+    let mut host_decoder = Decoder::default();
+    let mut dongle_decoder = Decoder::default();
+
+    let mut host_buffer = [0u8; 1024];
+    let mut dongle_buffer = [0u8; 1024];
+
+    let mut last_side = None;
+
+    // Kept across iterations: Ctrl-C is otherwise missed while frames are being forwarded.
+    let interrupted = signal::ctrl_c();
+    tokio::pin!(interrupted);
+
+    loop {
+        let (side, read) = select! {
+            read = host_rx.read(&mut host_buffer) => (Side::Host, read),
+            read = dongle_rx.read(&mut dongle_buffer) => (Side::Dongle, read),
+
+            _ = &mut interrupted => {
+                flush(started, &mut host_decoder, &mut dongle_decoder);
+                return;
+            }
+        };
+
+        let (bytes, decoder, other_decoder, writer) = match (side, read) {
+            (Side::Host, Ok(n)) if n > 0 => (
+                &host_buffer[..n],
+                &mut host_decoder,
+                &mut dongle_decoder,
+                &mut dongle_tx,
+            ),
+
+            (Side::Dongle, Ok(n)) if n > 0 => (
+                &dongle_buffer[..n],
+                &mut dongle_decoder,
+                &mut host_decoder,
+                &mut host_tx,
+            ),
+
+            (_, read) => {
+                flush(started, &mut host_decoder, &mut dongle_decoder);
+
+                match read {
+                    Err(e) => println!("Failed to read from {:?}: {}", side, e),
+                    Ok(_) => println!("{:?} went away", side),
+                }
+
+                return;
+            }
+        };
+
+        if let Err(e) = writer.write_all(bytes).await {
+            println!("Failed to forward what {:?} sent: {}", side, e);
+            exit(-1);
+        }
+
+        // Whatever the other side sent is complete, now that this one answers.
+        if last_side != Some(side) {
+            let other_side = match side {
+                Side::Host => Side::Dongle,
+                Side::Dongle => Side::Host,
+            };
+
+            if let Some(frame) = other_decoder.flush() {
+                log(started, other_side, frame);
+            }
+
+            last_side = Some(side);
+        }
+
+        decoder.push(bytes);
+
+        while let Some(frame) = decoder.next_frame() {
+            log(started, side, frame);
+        }
+    }
+}
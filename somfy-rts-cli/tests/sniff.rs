@@ -0,0 +1,127 @@
+#![cfg(unix)]
+
+use std::{
+    io::{BufRead, BufReader, Read},
+    os::unix::io::RawFd,
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    pty::openpty,
+    sys::{
+        signal::{kill, Signal},
+        termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg},
+    },
+    unistd::{read, ttyname, write, Pid},
+};
+
+/// How long to wait for the sniffer to forward bytes, before deciding it does not.
+const TIMEOUT_MS: i32 = 5000;
+
+/// A raw pseudo-terminal: its master side, played by the test, and the path of the other.
+fn pty() -> (RawFd, PathBuf) {
+    let pty = openpty(None, None).unwrap();
+
+    let mut termios = tcgetattr(pty.slave).unwrap();
+    cfmakeraw(&mut termios);
+    tcsetattr(pty.slave, SetArg::TCSANOW, &termios).unwrap();
+
+    (pty.master, ttyname(pty.slave).unwrap())
+}
+
+fn send(fd: RawFd, mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        bytes = &bytes[write(fd, bytes).unwrap()..];
+    }
+}
+
+fn receive(fd: RawFd, len: usize) -> Vec<u8> {
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 1024];
+
+    while received.len() < len {
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+
+        assert_eq!(
+            poll(&mut fds, TIMEOUT_MS).unwrap(),
+            1,
+            "Nothing was forwarded"
+        );
+
+        let n = read(fd, &mut buffer[..len - received.len()]).unwrap();
+        received.extend_from_slice(&buffer[..n]);
+    }
+
+    received
+}
+
+#[test]
+fn forwards_and_logs_both_ways() {
+    let (host, host_path) = pty();
+    let (dongle, dongle_path) = pty();
+
+    let mut sniffer = Command::new(env!("CARGO_BIN_EXE_somfy-rts-cli"))
+        .arg("sniff")
+        .args([&host_path, &dongle_path])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut output = BufReader::new(sniffer.stdout.take().unwrap());
+
+    // Both ports are set up once the sniffer says so.
+    let mut line = String::new();
+
+    while !line.starts_with("Sniffing") {
+        line.clear();
+        assert!(output.read_line(&mut line).unwrap() > 0, "Sniffer stopped");
+    }
+
+    let init = b"$CRYPTO_OFF_3145";
+    let init_answer = b"RTSDONGLE,OK,1.0.0\r\n";
+
+    send(host, init);
+    assert_eq!(receive(dongle, init.len()), init);
+
+    send(dongle, init_answer);
+    assert_eq!(receive(host, init_answer.len()), init_answer);
+
+    // A command split across writes still makes a single frame.
+    let command = br#"{"CMD-DONGLE":"ALIVE"}"#;
+    let answer =
+        br#"{"ACK":"DONGLE_OK","ID":["SIM0000001","1.0.0","SOMFY-RTS-SIM"],"RSSI-VAL":-60}"#;
+
+    send(host, &command[..10]);
+    thread::sleep(Duration::from_millis(50));
+    send(host, &command[10..]);
+    assert_eq!(receive(dongle, command.len()), command);
+
+    send(dongle, answer);
+    assert_eq!(receive(host, answer.len()), answer);
+
+    kill(Pid::from_raw(sniffer.id() as i32), Signal::SIGINT).unwrap();
+
+    let mut log = String::new();
+    output.read_to_string(&mut log).unwrap();
+
+    assert!(sniffer.wait().unwrap().success());
+
+    let frames = log
+        .lines()
+        .filter_map(|line| line.split_once("] ").map(|(_, frame)| frame))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        frames,
+        [
+            r#"host   -> dongle  $ message: "$CRYPTO_OFF_3145" [2443525950544f5f4f46465f33313435]"#,
+            "dongle -> host    init answer: RTSDONGLE,OK,1.0.0",
+            r#"host   -> dongle  CMD-DONGLE command: {"CMD-DONGLE":"ALIVE"}"#,
+            r#"dongle -> host    answer DONGLE_OK: {"ACK":"DONGLE_OK","ID":["SIM0000001","1.0.0","SOMFY-RTS-SIM"],"RSSI-VAL":-60}"#,
+        ]
+    );
+}
@@ -0,0 +1,888 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use futures::future;
+use log::{debug, info, trace, warn};
+use rumqttc::{AsyncClient, ConnectionError, Event, EventLoop, Packet, Publish};
+use serde_json::Value;
+use somfy_rts::{AddressVal, Ready, RtsCommand, SomfyRTSDongle};
+use tokio::{sync::mpsc, time};
+
+use crate::{
+    command::{Command, Order},
+    config::Config,
+    cover::{self, Cover, Tilt},
+    events::Events,
+    ha,
+    homie::{self, Homie},
+    metrics,
+    queue::{self, CommandQueue, Completion, Failure, Outcome, Reply, Request},
+    schedule::Scheduler,
+    somfy::DongleInfo,
+    topics::{Route, Topics},
+};
+
+/// Wait before connecting to the broker again, once a connection failed.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+pub struct Bridge {
+    client: AsyncClient,
+    queue: CommandQueue,
+    completions: mpsc::UnboundedReceiver<Completion>,
+    config: Config,
+    topics: Topics,
+    dongle_id: String,
+    /// Address of the blind held by each slot in use.
+    slots: BTreeMap<u8, String>,
+    /// Slots a blind is being paired with, kept from other pairings until the dongle answers.
+    reserved: BTreeSet<u8>,
+    covers: BTreeMap<u8, Cover>,
+    tilts: BTreeMap<u8, Tilt>,
+    errors: u64,
+    homie: Option<Homie>,
+    scheduler: Scheduler,
+}
+
+impl Bridge {
+    pub fn new(
+        client: AsyncClient,
+        dongle: SomfyRTSDongle<Ready>,
+        info: &DongleInfo,
+        blinds: &[(u8, AddressVal)],
+        config: Config,
+        events: Events,
+        homie: Option<Homie>,
+    ) -> Self {
+        let slots = blinds
+            .iter()
+            .filter_map(|(id, blind)| Some((*id, blind.address_val[1].as_str()?.to_string())))
+            .collect();
+
+        let covers = config
+            .blinds
+            .iter()
+            .filter_map(|(id, blind)| Some((*id, Cover::new(blind.travel_time.as_ref()?))))
+            .collect();
+
+        let tilts = config
+            .blinds
+            .iter()
+            .filter_map(|(id, blind)| Some((*id, Tilt::new(blind.tilt_time.as_ref()?))))
+            .collect();
+
+        let (queue, completions) = queue::spawn(
+            dongle,
+            info,
+            config.frame_spacing,
+            config.record.clone(),
+            events,
+        );
+
+        Bridge {
+            client,
+            queue,
+            completions,
+            topics: config.topics(),
+            scheduler: Scheduler::new(&config),
+            config,
+            dongle_id: info.serial().to_string(),
+            slots,
+            reserved: BTreeSet::new(),
+            covers,
+            tilts,
+            errors: 0,
+            homie,
+        }
+    }
+
+    /// Queue of the requests for the dongle, shared with other front-ends.
+    pub fn queue(&self) -> CommandQueue {
+        self.queue.clone()
+    }
+
+    /// Runs the bridge, along with the event loop of its Homie device if it has one.
+    pub async fn run(
+        mut self,
+        mut eventloop: EventLoop,
+        mut homie: Option<EventLoop>,
+    ) -> Result<()> {
+        let mut rssi_poll = time::interval(self.config.rssi_interval);
+
+        for rule in self.scheduler.rules() {
+            ha::publish_schedule(&self.client, &self.topics, rule).await?;
+        }
+
+        // Losing the broker is not worth stopping the bridge for: event loops connect again on
+        // the next poll, which waits a bit once they failed.
+        let mut retry = None;
+        let mut homie_retry = None;
+
+        loop {
+            tokio::select! {
+                notification = poll(Some(&mut eventloop), retry) => match notification {
+                    Ok(notification) => {
+                        retry = None;
+
+                        trace!(target:"bridge", "Received = {:?}", notification);
+
+                        match notification {
+                            Event::Incoming(Packet::Publish(data)) => self.on_publish(data).await?,
+
+                            Event::Incoming(Packet::ConnAck(connack))
+                                if !connack.session_present =>
+                            {
+                                self.on_connected().await?
+                            }
+
+                            _ => {}
+                        }
+                    }
+
+                    Err(e) => {
+                        warn!(
+                            target:"bridge",
+                            "MQTT connection failed, trying again in {}: {}",
+                            humantime::format_duration(RETRY_DELAY),
+                            e
+                        );
+
+                        retry = Some(Instant::now() + RETRY_DELAY);
+                    }
+                },
+
+                notification = poll(homie.as_mut(), homie_retry) => match notification {
+                    Ok(notification) => {
+                        homie_retry = None;
+
+                        trace!(target:"bridge", "Received from Homie = {:?}", notification);
+
+                        match notification {
+                            Event::Incoming(Packet::Publish(data)) => {
+                                self.on_homie_publish(data).await?
+                            }
+
+                            Event::Incoming(Packet::ConnAck(connack))
+                                if !connack.session_present =>
+                            {
+                                self.resubscribe_homie().await?
+                            }
+
+                            _ => {}
+                        }
+                    }
+
+                    Err(e) => {
+                        warn!(
+                            target:"bridge",
+                            "Homie connection failed, trying again in {}: {}",
+                            humantime::format_duration(RETRY_DELAY),
+                            e
+                        );
+
+                        homie_retry = Some(Instant::now() + RETRY_DELAY);
+                    }
+                },
+
+                completion = self.completions.recv() => match completion {
+                    Some(completion) => self.on_completion(completion).await?,
+                    None => return Err(anyhow!("Lost the dongle")),
+                },
+
+                _ = rssi_poll.tick() => self.push(Request::Alive).await?,
+
+                _ = wait_until(self.scheduler.next()) => self.run_schedules().await?,
+            }
+        }
+    }
+
+    /// Subscriptions do not outlive the session, which a reconnection may not resume, and the
+    /// last will may have marked the dongle offline in the meantime.
+    async fn on_connected(&self) -> Result<()> {
+        ha::resubscribe(
+            &self.client,
+            &self.slot_ids(),
+            &self.dongle_id,
+            &self.config,
+        )
+        .await?;
+
+        ha::set_state_on(&self.client, &self.topics).await
+    }
+
+    async fn on_publish(&mut self, data: Publish) -> Result<()> {
+        debug!(target:"bridge", "{} -> {:?}", data.topic, data.payload);
+
+        metrics::mqtt_received(&self.dongle_id);
+
+        let Some(route) = self.topics.route(&data.topic) else {
+            warn!(target:"bridge", "Received message with bad topic: '{}'", data.topic);
+            self.reject();
+            return Ok(());
+        };
+
+        let payload = std::str::from_utf8(&data.payload);
+
+        match route {
+            Route::DiscoveryConfig {
+                component,
+                node_id,
+                object_id,
+            } => {
+                // Only configs retained on the broker can be stale.
+                if !data.retain || data.payload.is_empty() {
+                    return Ok(());
+                }
+
+                self.on_discovery_config(&data.topic, component, node_id, object_id)
+                    .await
+            }
+
+            Route::Reboot => {
+                ha::set_state_off(&self.client, &self.topics).await?;
+
+                if let Some(homie) = self.homie.as_ref() {
+                    homie.set_state(homie::State::Init).await?;
+                }
+
+                self.push(Request::Reboot).await
+            }
+
+            Route::Pair => match reserve_slot(&self.slots, &mut self.reserved) {
+                Some(id) => {
+                    info!(target:"bridge", "Pairing a new blind in slot {}.", id);
+                    self.push(Request::Pair(id)).await
+                }
+
+                None => {
+                    warn!(target:"bridge", "Cannot pair a new blind: every slot is in use");
+                    Ok(())
+                }
+            },
+
+            Route::Unpair(id) => match id.parse::<u8>() {
+                Ok(id) if self.slots.contains_key(&id) => self.push(Request::Unpair(id)).await,
+
+                _ => {
+                    warn!(target:"bridge", "Received unpair order for bad slot: '{}'", id);
+                    self.reject();
+                    Ok(())
+                }
+            },
+
+            Route::GroupSet(name) => match payload.map(str::parse::<Command>) {
+                Ok(Ok(command)) => self.operate_group(name, command).await,
+
+                _ => {
+                    warn!(target:"bridge", "Received group message with bad payload: {:?}", data.payload);
+                    self.reject();
+                    Ok(())
+                }
+            },
+
+            Route::SceneSet(name) => self.run_scene(name).await,
+
+            Route::ScheduleSet(name) => {
+                let enabled = match payload {
+                    Ok("ON") => true,
+                    Ok("OFF") => false,
+
+                    _ => {
+                        warn!(target:"bridge", "Received schedule message with bad payload: {:?}", data.payload);
+                        self.reject();
+                        return Ok(());
+                    }
+                };
+
+                match self.scheduler.enable(name, enabled) {
+                    Some(rule) => {
+                        info!(target:"bridge", "Schedule '{}' turned {}.", name, if enabled { "on" } else { "off" });
+                        ha::publish_schedule(&self.client, &self.topics, rule).await
+                    }
+
+                    None => {
+                        warn!(target:"bridge", "Received order for unknown schedule '{}'", name);
+                        self.reject();
+                        Ok(())
+                    }
+                }
+            }
+
+            Route::CoverTilt(id) => {
+                let Some(id) = blind_id(&data.topic, id) else {
+                    self.reject();
+                    return Ok(());
+                };
+
+                match payload.map(str::parse::<f64>) {
+                    Ok(Ok(angle)) if angle.is_finite() => self.tilt(id, angle).await,
+
+                    _ => {
+                        warn!(target:"bridge", "Received tilt message with bad payload: {:?}", data.payload);
+                        self.reject();
+                        Ok(())
+                    }
+                }
+            }
+
+            Route::CoverSet(id) => {
+                let Some(id) = blind_id(&data.topic, id) else {
+                    self.reject();
+                    return Ok(());
+                };
+
+                match payload.map(str::parse::<Command>) {
+                    Ok(Ok(command)) => self.operate(id, command).await,
+
+                    Err(e) => {
+                        warn!(target:"bridge", "Received message with bad payload: {}", e);
+                        self.reject();
+                        Ok(())
+                    }
+
+                    Ok(Err(e)) => {
+                        warn!(target:"bridge", "Received message with {}", e);
+                        self.reject();
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles commands set on the `command` property of a Homie node.
+    async fn on_homie_publish(&mut self, data: Publish) -> Result<()> {
+        debug!(target:"bridge", "{} -> {:?}", data.topic, data.payload);
+
+        metrics::mqtt_received(&self.dongle_id);
+
+        let Some(id) = self
+            .homie
+            .as_ref()
+            .and_then(|homie| homie.route(&data.topic))
+        else {
+            warn!(target:"bridge", "Received Homie message with bad topic: '{}'", data.topic);
+            self.reject();
+            return Ok(());
+        };
+
+        let Some(id) = blind_id(&data.topic, id) else {
+            self.reject();
+            return Ok(());
+        };
+
+        match std::str::from_utf8(&data.payload).map(str::parse::<Command>) {
+            Ok(Ok(command)) => self.operate(id, command).await,
+
+            _ => {
+                warn!(target:"bridge", "Received Homie command with bad payload: {:?}", data.payload);
+                self.reject();
+                Ok(())
+            }
+        }
+    }
+
+    /// Deletes configs retained on the broker for slots that are no longer paired.
+    async fn on_discovery_config(
+        &self,
+        topic: &str,
+        component: &str,
+        node_id: &str,
+        object_id: &str,
+    ) -> Result<()> {
+        if node_id != self.dongle_id
+            || !ha::is_stale_config(component, object_id, &self.slots, &self.config)
+        {
+            return Ok(());
+        }
+
+        info!(target:"bridge", "Removing stale {} '{}' from Home Assistant.", component, object_id);
+
+        ha::delete_config(&self.client, topic).await
+    }
+
+    /// Queues the same order for every member of a group, then reports, once they all went
+    /// through, which ones the dongle acknowledged.
+    async fn operate_group(&mut self, name: &str, command: Command) -> Result<()> {
+        let Some(members) = self.config.group(name).map(<[u8]>::to_vec) else {
+            warn!(target:"bridge", "Received order for unknown group '{}'", name);
+            self.reject();
+            return Ok(());
+        };
+
+        if command.then.is_some() {
+            warn!(target:"bridge", "Received follow-up order for group '{}': follow-ups only work on single blinds", name);
+            self.reject();
+            return Ok(());
+        }
+
+        let outcomes = members
+            .iter()
+            .map(|id| {
+                self.queue
+                    .push(command.command.request(*id, command.repeat))
+            })
+            .collect::<Vec<_>>();
+
+        self.publish_queue_depth().await?;
+
+        let client = self.client.clone();
+        let topics = self.topics.clone();
+        let name = name.to_string();
+
+        tokio::spawn(async move {
+            let mut succeeded = Vec::with_capacity(members.len());
+            let mut superseded = Vec::new();
+            let mut failed = Vec::new();
+
+            for (id, outcome) in members.into_iter().zip(future::join_all(outcomes).await) {
+                match outcome {
+                    Ok(Ok(_)) => succeeded.push(id),
+                    Ok(Err(Failure::Superseded)) => superseded.push(id),
+                    _ => failed.push(id),
+                }
+            }
+
+            if !failed.is_empty() {
+                warn!(
+                    target:"bridge",
+                    "Group '{}': {} of {} blinds failed ({:?})",
+                    name,
+                    failed.len(),
+                    failed.len() + succeeded.len() + superseded.len(),
+                    failed
+                );
+            }
+
+            let published = ha::publish_group_result(
+                &client,
+                &topics,
+                &name,
+                &succeeded,
+                &superseded,
+                &failed,
+                command.correlation_id.as_ref(),
+            )
+            .await;
+
+            if let Err(e) = published {
+                warn!(target:"bridge", "Failed to publish result of group '{}': {}", name, e);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Runs a scene in the background, then publishes whether it went through.
+    async fn run_scene(&mut self, name: &str) -> Result<()> {
+        let Some(actions) = self.config.scene(name) else {
+            warn!(target:"bridge", "Received order for unknown scene '{}'", name);
+            self.reject();
+            return Ok(());
+        };
+
+        info!(target:"bridge", "Running scene '{}'.", name);
+
+        let client = self.client.clone();
+        let topics = self.topics.clone();
+        let queue = self.queue.clone();
+        let name = name.to_string();
+
+        tokio::spawn(async move {
+            let outcome = queue.run_scene(&actions).await;
+
+            if let Err(e) = outcome.as_ref() {
+                warn!(target:"bridge", "Scene '{}' aborted: {}", name, e);
+            }
+
+            if let Err(e) = ha::publish_scene_result(&client, &topics, &name, &outcome).await {
+                warn!(target:"bridge", "Failed to publish result of scene '{}': {}", name, e);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Sends the orders of every schedule now due, then tells when each one next runs.
+    async fn run_schedules(&mut self) -> Result<()> {
+        for rule in self.scheduler.due(Local::now()) {
+            info!(target:"bridge", "Running schedule '{}': {}.", rule.name, rule.command.as_str());
+
+            for id in &rule.blinds {
+                self.operate(*id, rule.command.into()).await?;
+            }
+
+            for name in &rule.groups {
+                self.operate_group(name, rule.command.into()).await?;
+            }
+
+            ha::publish_schedule(&self.client, &self.topics, &rule).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Queues a command for a blind, then publishes how it went once the dongle sent it
+    /// (or a newer command replaced it).
+    ///
+    /// A follow-up order is queued once its delay elapsed, unless another request for the
+    /// same blind was queued in the meantime, whichever front-end it came from.
+    async fn operate(&mut self, id: u8, command: Command) -> Result<()> {
+        let outcome = self.queue.push(command.command.request(id, command.repeat));
+
+        self.publish_queue_depth().await?;
+
+        let follow_up = command.then.map(|then| (then, self.queue.follow_up(id)));
+
+        let client = self.client.clone();
+        let topics = self.topics.clone();
+        let queue = self.queue.clone();
+
+        tokio::spawn(async move {
+            let correlation_id = command.correlation_id.as_ref();
+
+            // The reply is only dropped when the dongle is lost, which stops the bridge anyway.
+            let Ok(outcome) = outcome.await else {
+                return;
+            };
+
+            report(
+                &client,
+                &topics,
+                id,
+                command.command,
+                &outcome,
+                correlation_id,
+            )
+            .await;
+
+            let Some((then, mut follow_up)) = follow_up else {
+                return;
+            };
+
+            if outcome.is_err() {
+                queue.follow_up_done(follow_up);
+                return;
+            }
+
+            tokio::select! {
+                _ = time::sleep(Duration::from_millis(then.after_ms)) => {}
+
+                // Dropped once a newer command for the blind is queued.
+                _ = &mut follow_up.cancelled => {
+                    debug!(target:"bridge", "Follow-up {} for blind {} cancelled", then.command.as_str(), id);
+                    return;
+                }
+            }
+
+            let request = then.command.request(id, then.repeat);
+
+            // A newer command may have come in just as the delay elapsed.
+            let Some(outcome) = queue.push_follow_up(follow_up, request) else {
+                debug!(target:"bridge", "Follow-up {} for blind {} cancelled", then.command.as_str(), id);
+                return;
+            };
+
+            if let Err(e) = ha::publish_queue_depth(&client, &topics, queue.len()).await {
+                warn!(target:"bridge", "Failed to publish queue depth: {}", e);
+            }
+
+            let Ok(outcome) = outcome.await else {
+                return;
+            };
+
+            report(&client, &topics, id, then.command, &outcome, correlation_id).await;
+        });
+
+        Ok(())
+    }
+
+    async fn push(&mut self, request: Request) -> Result<()> {
+        // Outcomes come back through the completion channel.
+        drop(self.queue.push(request));
+
+        self.publish_queue_depth().await
+    }
+
+    async fn publish_queue_depth(&self) -> Result<()> {
+        ha::publish_queue_depth(&self.client, &self.topics, self.queue.len()).await
+    }
+
+    /// Keeps track of what the dongle did, and publishes how it went.
+    async fn on_completion(&mut self, Completion { request, outcome }: Completion) -> Result<()> {
+        self.publish_queue_depth().await?;
+
+        // Paired or not, the slot is now known for what it is.
+        if let Request::Pair(id) = request {
+            self.reserved.remove(&id);
+        }
+
+        let topics = &self.topics;
+
+        match (request, outcome) {
+            (Request::Alive, Ok(Reply::Alive(alive))) => {
+                metrics::rssi(&self.dongle_id, alive.rssi_val);
+
+                ha::publish_rssi(&self.client, topics, alive.rssi_val).await
+            }
+
+            (Request::Alive, Err(e)) => {
+                warn!(target:"bridge", "Failed to poll dongle RSSI: {}", e);
+                Ok(())
+            }
+
+            (Request::Reboot, _) => {
+                if let Some(homie) = self.homie.as_ref() {
+                    homie.set_state(homie::State::Ready).await?;
+                }
+
+                ha::set_state_on(&self.client, topics).await
+            }
+
+            (request, Err(e)) => {
+                warn!(target:"bridge", "Order {:?} failed: {}", request, e);
+                self.errors += 1;
+
+                // The slats may have moved through some of the pulses, unless none was sent.
+                if let (Request::Tilt(id, ..), Failure::Refused(_) | Failure::Error(_)) =
+                    (&request, &e)
+                {
+                    if let Some(tilt) = self.tilts.get_mut(id) {
+                        tilt.lost();
+                    }
+                }
+
+                ha::publish_command_result(&self.client, topics, &e.to_string(), self.errors).await
+            }
+
+            (request, Ok(reply)) => {
+                ha::publish_command_result(&self.client, topics, "OK", self.errors).await?;
+
+                match (request, reply) {
+                    (Request::Operate(command) | Request::Repeat(command, _), _) => {
+                        self.track(command).await
+                    }
+                    (Request::Tilt(id, angle, _), _) => {
+                        if let Some(tilt) = self.tilts.get_mut(&id) {
+                            tilt.reached(angle);
+                        }
+
+                        self.publish_tilt(id).await
+                    }
+                    (Request::Pair(id), Reply::Blind(blind)) => self.on_paired(id, blind).await,
+                    (Request::Unpair(id), _) => self.on_unpaired(id).await,
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Announces a freshly paired blind, without waiting for a restart.
+    async fn on_paired(&mut self, id: u8, blind: AddressVal) -> Result<()> {
+        let Some(addr) = blind.address_val[1].as_str() else {
+            warn!(target:"bridge", "Dongle gave no address to blind paired in slot {}", id);
+            return Ok(());
+        };
+
+        info!(target:"bridge", "Paired blind {} in slot {}.", addr, id);
+
+        ha::announce_blind(&self.client, id, addr, &self.dongle_id, &self.config).await?;
+
+        self.slots.insert(id, addr.to_string());
+
+        match self.homie.as_ref() {
+            Some(homie) => homie.add_node(id, &self.slot_ids(), &self.config).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn on_unpaired(&mut self, id: u8) -> Result<()> {
+        let Some(addr) = self.slots.remove(&id) else {
+            return Ok(());
+        };
+
+        info!(target:"bridge", "Unpaired blind {} from slot {}.", addr, id);
+
+        ha::withdraw_blind(&self.client, id, &addr, &self.dongle_id, &self.config).await?;
+
+        match self.homie.as_ref() {
+            Some(homie) => homie.remove_node(id, &self.slot_ids(), &self.config).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Counts a message that could not be acted upon.
+    fn reject(&self) {
+        metrics::mqtt_rejected(&self.dongle_id);
+    }
+
+    async fn resubscribe_homie(&self) -> Result<()> {
+        match self.homie.as_ref() {
+            Some(homie) => homie.resubscribe(&self.slot_ids()).await,
+            None => Ok(()),
+        }
+    }
+
+    fn slot_ids(&self) -> Vec<u8> {
+        self.slots.keys().copied().collect()
+    }
+
+    /// Tilts the slats of a venetian blind through short presses on UP or DOWN.
+    async fn tilt(&mut self, id: u8, angle: f64) -> Result<()> {
+        let Some(tilt) = self.tilts.get(&id) else {
+            warn!(target:"bridge", "Received tilt order for blind {} which has no tilt configured", id);
+            self.reject();
+            return Ok(());
+        };
+
+        let pulses = tilt.pulses_to(angle);
+
+        self.push(Request::Tilt(id, angle, pulses)).await
+    }
+
+    async fn publish_tilt(&self, id: u8) -> Result<()> {
+        match self.tilts.get(&id).and_then(Tilt::angle) {
+            Some(angle) => ha::publish_tilt(&self.client, &self.topics, id, angle).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Updates the estimated position of a blind after a command went through.
+    async fn track(&mut self, command: RtsCommand) -> Result<()> {
+        let now = Instant::now();
+
+        let id = match command {
+            RtsCommand::Up(id)
+            | RtsCommand::Down(id)
+            | RtsCommand::Stop(id)
+            | RtsCommand::My(id) => id,
+            _ => return Ok(()),
+        };
+
+        let Some(cover) = self.covers.get_mut(&id) else {
+            return Ok(());
+        };
+
+        let my_position = self.config.blind(id).and_then(|blind| blind.my_position);
+
+        match command {
+            RtsCommand::Up(_) => cover.move_to(cover::OPEN, now),
+            RtsCommand::Down(_) => cover.move_to(cover::CLOSED, now),
+
+            RtsCommand::Stop(_) => cover.stop(now),
+
+            RtsCommand::My(_) => {
+                if !cover.my(my_position.map(f64::from), now) {
+                    return Ok(());
+                }
+            }
+
+            _ => return Ok(()),
+        }
+
+        let Some(position) = cover.destination(now) else {
+            return Ok(());
+        };
+
+        if let Some(homie) = self.homie.as_ref() {
+            homie.publish_position(id, position).await?;
+        }
+
+        ha::publish_position(&self.client, &self.topics, id, position).await
+    }
+}
+
+/// Polls an event loop, if there is one, not before `not_before`: otherwise, never resolves.
+async fn poll(
+    eventloop: Option<&mut EventLoop>,
+    not_before: Option<Instant>,
+) -> Result<Event, ConnectionError> {
+    match eventloop {
+        Some(eventloop) => {
+            if let Some(not_before) = not_before {
+                time::sleep_until(not_before.into()).await;
+            }
+
+            eventloop.poll().await
+        }
+
+        None => future::pending().await,
+    }
+}
+
+/// Waits until `time`, if there is one: otherwise, never resolves.
+async fn wait_until(time: Option<DateTime<Local>>) {
+    match time {
+        Some(time) => time::sleep((time - Local::now()).to_std().unwrap_or_default()).await,
+        None => future::pending().await,
+    }
+}
+
+/// Parses the blind id found in `topic`, warning about it when it is not one.
+fn blind_id(topic: &str, id: &str) -> Option<u8> {
+    match id.parse::<u8>() {
+        Ok(id) => Some(id),
+
+        Err(e) => {
+            warn!(target:"bridge", "Received message with bad blind id: '{}' ({})", topic, e);
+            None
+        }
+    }
+}
+
+/// Picks the first slot neither in use nor reserved for a pairing, and reserves it.
+fn reserve_slot(slots: &BTreeMap<u8, String>, reserved: &mut BTreeSet<u8>) -> Option<u8> {
+    let id = (1..=100).find(|id| !slots.contains_key(id) && !reserved.contains(id))?;
+
+    reserved.insert(id);
+
+    Some(id)
+}
+
+/// Publishes how a command sent to a blind went.
+async fn report(
+    client: &AsyncClient,
+    topics: &Topics,
+    id: u8,
+    order: Order,
+    outcome: &Outcome,
+    correlation_id: Option<&Value>,
+) {
+    let published =
+        ha::publish_cover_result(client, topics, id, order, outcome, correlation_id).await;
+
+    if let Err(e) = published {
+        warn!(target:"bridge", "Failed to publish result for blind {}: {}", id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairings_in_a_row_get_their_own_slot() {
+        let slots = BTreeMap::from([(1, "0A0B0C".to_string()), (3, "0D0E0F".to_string())]);
+        let mut reserved = BTreeSet::new();
+
+        assert_eq!(reserve_slot(&slots, &mut reserved), Some(2));
+        assert_eq!(reserve_slot(&slots, &mut reserved), Some(4));
+
+        // The first pairing failed: its slot can be used again.
+        reserved.remove(&2);
+
+        assert_eq!(reserve_slot(&slots, &mut reserved), Some(2));
+    }
+
+    #[test]
+    fn no_slot_is_left_once_all_are_reserved() {
+        let slots = BTreeMap::new();
+        let mut reserved = (1..=99).collect();
+
+        assert_eq!(reserve_slot(&slots, &mut reserved), Some(100));
+        assert_eq!(reserve_slot(&slots, &mut reserved), None);
+    }
+}
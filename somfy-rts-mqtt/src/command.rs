@@ -0,0 +1,152 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde_json::Value;
+use somfy_rts::RtsCommand;
+
+use crate::queue::Request;
+
+/// Orders understood on command topics.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[serde(alias = "UP")]
+    Up,
+
+    #[serde(alias = "DOWN")]
+    Down,
+
+    #[serde(alias = "STOP")]
+    Stop,
+
+    #[serde(alias = "MY")]
+    My,
+}
+
+/// Most times a frame can be sent over for a single command.
+pub const MAX_REPEAT: u8 = 10;
+
+/// A command received on a cover or group topic.
+///
+/// It is either a bare order (`UP`), or a JSON object such as
+/// `{"command": "down", "repeat": 2, "then": {"command": "stop", "after_ms": 3500}}`.
+/// Its correlation id, if any, is sent back along with the result.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Command {
+    pub command: Order,
+
+    /// How many times the frame is sent, for objects that are hard to reach.
+    #[serde(default = "once")]
+    pub repeat: u8,
+
+    /// Another order to send once this one went through.
+    pub then: Option<FollowUp>,
+
+    #[serde(default)]
+    pub correlation_id: Option<Value>,
+}
+
+/// An order sent some time after a command, unless a newer command for the same blind
+/// came in between.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FollowUp {
+    pub command: Order,
+
+    #[serde(default = "once")]
+    pub repeat: u8,
+
+    /// Delay since the dongle acknowledged the first command.
+    #[serde(default)]
+    pub after_ms: u64,
+}
+
+fn once() -> u8 {
+    1
+}
+
+fn check_repeat(repeat: u8) -> Result<(), String> {
+    if (1..=MAX_REPEAT).contains(&repeat) {
+        Ok(())
+    } else {
+        Err(format!(
+            "bad command: repeat must be between 1 and {}, not {}",
+            MAX_REPEAT, repeat
+        ))
+    }
+}
+
+impl Order {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Up => "UP",
+            Self::Down => "DOWN",
+            Self::Stop => "STOP",
+            Self::My => "MY",
+        }
+    }
+
+    /// What the dongle has to do to send this order to blind `id`, `repeat` times.
+    pub fn request(&self, id: u8, repeat: u8) -> Request {
+        match repeat {
+            1 => Request::Operate(self.rts(id)),
+            _ => Request::Repeat(self.rts(id), repeat),
+        }
+    }
+
+    pub fn rts(&self, id: u8) -> RtsCommand {
+        match self {
+            Self::Up => RtsCommand::Up(id),
+            Self::Down => RtsCommand::Down(id),
+            Self::Stop => RtsCommand::Stop(id),
+            Self::My => RtsCommand::My(id),
+        }
+    }
+}
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str(payload: &str) -> Result<Self, Self::Err> {
+        match payload {
+            "UP" => Ok(Self::Up),
+            "DOWN" => Ok(Self::Down),
+            "STOP" => Ok(Self::Stop),
+            "MY" => Ok(Self::My),
+            _ => Err(format!("unknown order '{}'", payload)),
+        }
+    }
+}
+
+impl From<Order> for Command {
+    fn from(command: Order) -> Self {
+        Command {
+            command,
+            repeat: once(),
+            then: None,
+            correlation_id: None,
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(payload: &str) -> Result<Self, Self::Err> {
+        if !payload.trim_start().starts_with('{') {
+            return payload.parse::<Order>().map(Command::from);
+        }
+
+        let command: Command =
+            serde_json::from_str(payload).map_err(|e| format!("bad command: {}", e))?;
+
+        check_repeat(command.repeat)?;
+
+        if let Some(then) = command.then.as_ref() {
+            check_repeat(then.repeat)?;
+        }
+
+        Ok(command)
+    }
+}
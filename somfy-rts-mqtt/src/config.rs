@@ -0,0 +1,1310 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use log::LevelFilter;
+use rumqttc::{MqttOptions, TlsConfiguration};
+use serde::{Deserialize, Deserializer};
+use somfy_rts::{rpc, scene::Action};
+
+use crate::{command::Order, scene::Scene, schedule, tls, topics::Topics, Cli};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Serial port names of the dongles. If none is provided, every dongle found is used.
+    #[serde(deserialize_with = "one_or_many")]
+    pub serial: Vec<String>,
+
+    pub log_level: Option<LevelFilter>,
+
+    /// How often the dongle's signal strength is polled and published.
+    #[serde(with = "humantime_serde")]
+    pub rssi_interval: Duration,
+
+    pub mqtt: MqttConfig,
+
+    pub homeassistant: HomeAssistantConfig,
+
+    pub homie: HomieConfig,
+
+    pub http: HttpConfig,
+
+    pub control: ControlConfig,
+
+    /// Per-slot settings, keyed by the blind id on the dongle (1 to 100).
+    #[serde(deserialize_with = "slot_map")]
+    pub blinds: BTreeMap<u8, BlindConfig>,
+
+    /// Named sets of blinds, operated together.
+    #[serde(deserialize_with = "group_map")]
+    pub groups: BTreeMap<String, Vec<u8>>,
+
+    /// Minimum pause between two frames sent by the dongle.
+    #[serde(with = "humantime_serde")]
+    pub frame_spacing: Duration,
+
+    /// Directory where the traffic with each dongle is recorded, to ship with bug reports.
+    pub record: Option<PathBuf>,
+
+    /// Where the blinds are, for schedules following the sun.
+    pub location: Option<Location>,
+
+    /// Orders sent at set times, keyed by name.
+    #[serde(deserialize_with = "schedule_map")]
+    pub schedules: BTreeMap<String, ScheduleConfig>,
+
+    /// Steps run one after the other on demand, keyed by name.
+    #[serde(deserialize_with = "scene_map")]
+    pub scenes: BTreeMap<String, Scene>,
+
+    /// Per-dongle settings, keyed by the serial number the dongle reports (not its port).
+    pub dongles: BTreeMap<String, DongleConfig>,
+}
+
+/// Settings of one dongle, when several are driven at once.
+///
+/// Each dongle has its own slots, so blinds and groups are declared here rather than at
+/// the top level, which only applies when a single dongle is driven.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DongleConfig {
+    /// Base of the dongle's topics, `<topic_prefix>/<serial number>` by default.
+    pub topic_prefix: Option<String>,
+
+    #[serde(deserialize_with = "slot_map")]
+    pub blinds: BTreeMap<u8, BlindConfig>,
+
+    #[serde(deserialize_with = "group_map")]
+    pub groups: BTreeMap<String, Vec<u8>>,
+
+    #[serde(deserialize_with = "schedule_map")]
+    pub schedules: BTreeMap<String, ScheduleConfig>,
+
+    #[serde(deserialize_with = "scene_map")]
+    pub scenes: BTreeMap<String, Scene>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    pub client_id: String,
+
+    pub host: Option<String>,
+
+    pub port: u16,
+
+    pub username: Option<String>,
+
+    /// File holding the password, so that it does not show up in `ps` or in service files.
+    pub password_file: Option<PathBuf>,
+
+    /// Password supplied on the command line, which takes precedence over `password_file`.
+    #[serde(skip)]
+    pub password: Option<String>,
+
+    pub topic_prefix: String,
+
+    /// Time after which the broker is pinged when nothing else was exchanged (5s at least).
+    #[serde(with = "humantime_serde")]
+    pub keep_alive: Duration,
+
+    /// How the broker is reached.
+    pub transport: Transport,
+
+    /// Path of the broker's WebSocket endpoint, used by the `ws` and `wss` transports.
+    pub ws_path: String,
+
+    pub tls: TlsConfig,
+}
+
+/// Local HTTP API, for whatever does not speak MQTT.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Address to serve the API on. It is not served when none is given.
+    pub listen: Option<SocketAddr>,
+}
+
+/// Local socket through which `somfy-rts-cli` drives the dongles while the bridge holds
+/// their ports.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlConfig {
+    pub enabled: bool,
+
+    pub socket: PathBuf,
+}
+
+/// Ways of reaching the broker, named after the matching URL schemes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// Plain TCP
+    #[default]
+    Mqtt,
+
+    /// TCP over TLS
+    Mqtts,
+
+    /// WebSocket
+    Ws,
+
+    /// WebSocket over TLS
+    Wss,
+}
+
+/// TLS settings, used by the `mqtts` and `wss` transports.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// CA bundle (PEM) the broker's certificate is checked against, instead of the system's.
+    pub ca_file: Option<PathBuf>,
+
+    /// Certificate (PEM) presented to brokers requiring client authentication.
+    pub client_cert: Option<PathBuf>,
+
+    /// Private key (PEM) of `client_cert`.
+    pub client_key: Option<PathBuf>,
+
+    /// Accepts any certificate from the broker, for self-signed ones.
+    pub insecure: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HomeAssistantConfig {
+    pub discovery_prefix: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Location {
+    pub latitude: f64,
+
+    pub longitude: f64,
+}
+
+/// An order sent to blinds and groups, either following a cron expression or around
+/// sunrise or sunset.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    pub command: Order,
+
+    #[serde(default)]
+    pub blinds: Vec<u8>,
+
+    #[serde(default)]
+    pub groups: Vec<String>,
+
+    /// Cron expression, with or without seconds (`0 7 * * Mon-Fri`).
+    pub cron: Option<String>,
+
+    pub sun: Option<Sun>,
+
+    /// Time from the sun event to the order, such as `-15m` to close before sunset.
+    #[serde(default, deserialize_with = "signed_duration")]
+    pub offset: chrono::TimeDelta,
+
+    /// Whether the schedule runs at startup. It can be turned on and off over MQTT.
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sun {
+    Sunrise,
+    Sunset,
+}
+
+/// Publishes the dongle following the Homie convention too, for controllers such as openHAB.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HomieConfig {
+    pub enabled: bool,
+
+    /// Base of the Homie topics, under which the dongle is `somfy-rts-<serial number>`.
+    pub base_topic: String,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlindConfig {
+    pub name: Option<String>,
+
+    /// Room suggested to Home Assistant for the blind's device.
+    pub area: Option<String>,
+
+    pub device_class: DeviceClass,
+
+    pub travel_time: Option<TravelTime>,
+
+    /// Position (0 = closed, 100 = open) the blind goes to on MY, used along `travel_time`.
+    pub my_position: Option<u8>,
+
+    /// Time taken by the slats of a venetian blind to tilt from one end to the other.
+    pub tilt_time: Option<TravelTime>,
+}
+
+/// Home Assistant cover device classes that make sense for RTS objects.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceClass {
+    Awning,
+    Blind,
+    Curtain,
+    Garage,
+    Gate,
+    #[default]
+    Shutter,
+}
+
+/// Time taken by a blind to go from one end of its course to the other.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TravelTime {
+    #[serde(with = "humantime_serde")]
+    pub open: Duration,
+
+    #[serde(with = "humantime_serde")]
+    pub close: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            serial: Vec::new(),
+            log_level: None,
+            rssi_interval: Duration::from_secs(60),
+            mqtt: MqttConfig::default(),
+            homeassistant: HomeAssistantConfig::default(),
+            homie: HomieConfig::default(),
+            http: HttpConfig::default(),
+            control: ControlConfig::default(),
+            blinds: BTreeMap::default(),
+            groups: BTreeMap::default(),
+            frame_spacing: Duration::from_millis(250),
+            record: None,
+            location: None,
+            schedules: BTreeMap::default(),
+            scenes: BTreeMap::default(),
+            dongles: BTreeMap::default(),
+        }
+    }
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            client_id: "somfy-rts-mqtt".to_string(),
+            host: None,
+            port: 1883,
+            username: None,
+            password_file: None,
+            password: None,
+            topic_prefix: "somfy-rts".to_string(),
+            keep_alive: Duration::from_secs(5),
+            transport: Transport::default(),
+            ws_path: "/mqtt".to_string(),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl Default for HomeAssistantConfig {
+    fn default() -> Self {
+        HomeAssistantConfig {
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+}
+
+impl Default for HomieConfig {
+    fn default() -> Self {
+        HomieConfig {
+            enabled: false,
+            base_topic: "homie".to_string(),
+        }
+    }
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig {
+            enabled: false,
+            socket: rpc::default_socket(),
+        }
+    }
+}
+
+/// Accepts a single value as well as a list of them.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+fn slot_map<'de, D>(deserializer: D) -> Result<BTreeMap<u8, BlindConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    BTreeMap::<String, BlindConfig>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(id, blind)| match (id.parse::<u8>(), blind.my_position) {
+            (Ok(id @ 1..=100), Some(position @ 101..)) => Err(D::Error::custom(format!(
+                "`{position}` isn't a valid MY position for blind {id}, expected 0 to 100"
+            ))),
+            (Ok(id @ 1..=100), _) => Ok((id, blind)),
+            _ => Err(D::Error::custom(format!(
+                "`{id}` isn't a valid blind id, expected 1 to 100"
+            ))),
+        })
+        .collect()
+}
+
+fn enabled() -> bool {
+    true
+}
+
+/// Parses durations such as `15m` or `-1h 30m`.
+fn signed_duration<'de, D>(deserializer: D) -> Result<chrono::TimeDelta, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let value = String::deserialize(deserializer)?;
+
+    let (sign, duration) = match value.trim().strip_prefix('-') {
+        Some(duration) => (-1, duration),
+        None => (1, value.trim().trim_start_matches('+')),
+    };
+
+    humantime::parse_duration(duration)
+        .ok()
+        .and_then(|duration| chrono::TimeDelta::from_std(duration).ok())
+        .map(|duration| duration * sign)
+        .ok_or_else(|| D::Error::custom(format!("`{value}` isn't a valid offset")))
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn schedule_map<'de, D>(deserializer: D) -> Result<BTreeMap<String, ScheduleConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let schedules = BTreeMap::<String, ScheduleConfig>::deserialize(deserializer)?;
+
+    for (name, schedule) in &schedules {
+        if !valid_name(name) {
+            return Err(D::Error::custom(format!(
+                "`{name}` isn't a valid schedule name, use only letters, digits, '_' and '-'"
+            )));
+        }
+
+        if let Some(id) = schedule.blinds.iter().find(|id| !(1..=100).contains(*id)) {
+            return Err(D::Error::custom(format!(
+                "`{id}` in schedule `{name}` isn't a valid blind id, expected 1 to 100"
+            )));
+        }
+
+        match (schedule.cron.as_deref(), schedule.sun) {
+            (Some(cron), None) => {
+                schedule::parse_cron(cron).map_err(|e| {
+                    D::Error::custom(format!("bad cron expression in schedule `{name}`: {e}"))
+                })?;
+            }
+
+            (None, Some(_)) => {}
+
+            _ => {
+                return Err(D::Error::custom(format!(
+                    "schedule `{name}` needs either `cron` or `sun`"
+                )))
+            }
+        }
+    }
+
+    Ok(schedules)
+}
+
+fn scene_map<'de, D>(deserializer: D) -> Result<BTreeMap<String, Scene>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let scenes = BTreeMap::<String, Scene>::deserialize(deserializer)?;
+
+    if let Some(name) = scenes.keys().find(|name| !valid_name(name)) {
+        return Err(D::Error::custom(format!(
+            "`{name}` isn't a valid scene name, use only letters, digits, '_' and '-'"
+        )));
+    }
+
+    Ok(scenes)
+}
+
+fn group_map<'de, D>(deserializer: D) -> Result<BTreeMap<String, Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let groups = BTreeMap::<String, Vec<u8>>::deserialize(deserializer)?;
+
+    for (name, members) in &groups {
+        if !valid_name(name) {
+            return Err(D::Error::custom(format!(
+                "`{name}` isn't a valid group name, use only letters, digits, '_' and '-'"
+            )));
+        }
+
+        if let Some(id) = members.iter().find(|id| !(1..=100).contains(*id)) {
+            return Err(D::Error::custom(format!(
+                "`{id}` in group `{name}` isn't a valid blind id, expected 1 to 100"
+            )));
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Wraps IPv6 addresses in brackets, to tell them from the port that follows.
+fn bracketed(host: &str) -> String {
+    if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    }
+}
+
+/// Prefixes are used as the first levels of topics we publish to, and subscribe to.
+fn check_prefix(what: &str, prefix: &str) -> Result<()> {
+    if prefix.is_empty()
+        || prefix.starts_with('/')
+        || prefix.ends_with('/')
+        || prefix.contains(['+', '#'])
+    {
+        return Err(anyhow!(
+            "`{prefix}` isn't a valid {what}: it must not be empty, hold wildcards, or start or end with '/'"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that schedules only target known groups, and that a location is known if any of
+/// them follows the sun.
+fn check_schedules(
+    schedules: &BTreeMap<String, ScheduleConfig>,
+    groups: &BTreeMap<String, Vec<u8>>,
+    has_location: bool,
+) -> Result<()> {
+    for (name, schedule) in schedules {
+        if let Some(group) = schedule
+            .groups
+            .iter()
+            .find(|group| !groups.contains_key(*group))
+        {
+            return Err(anyhow!("Schedule `{name}` targets unknown group `{group}`"));
+        }
+
+        if schedule.sun.is_some() && !has_location {
+            return Err(anyhow!(
+                "Schedule `{name}` follows the sun: set `latitude` and `longitude` in `[location]`"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that every scene can be resolved against the groups it may target.
+fn check_scenes(
+    scenes: &BTreeMap<String, Scene>,
+    groups: &BTreeMap<String, Vec<u8>>,
+) -> Result<()> {
+    for (name, scene) in scenes {
+        scene
+            .actions(groups)
+            .with_context(|| format!("Bad scene `{name}`"))?;
+    }
+
+    Ok(())
+}
+
+impl Sun {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sunrise => "sunrise",
+            Self::Sunset => "sunset",
+        }
+    }
+}
+
+impl Transport {
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            Self::Mqtt => "mqtt",
+            Self::Mqtts => "mqtts",
+            Self::Ws => "ws",
+            Self::Wss => "wss",
+        }
+    }
+}
+
+impl DeviceClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Awning => "awning",
+            Self::Blind => "blind",
+            Self::Curtain => "curtain",
+            Self::Garage => "garage",
+            Self::Gate => "gate",
+            Self::Shutter => "shutter",
+        }
+    }
+
+    pub fn model(&self) -> &'static str {
+        match self {
+            Self::Awning => "Awning",
+            Self::Blind => "Blind",
+            Self::Curtain => "Curtain",
+            Self::Garage => "Garage door",
+            Self::Gate => "Gate",
+            Self::Shutter => "Shutter",
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file '{}'", path.display()))?;
+
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file '{}'", path.display()))
+    }
+
+    /// Builds the configuration from the file given on the command line (if any),
+    /// then lets command line arguments override the values it holds.
+    pub fn from_cli(cli: &Cli) -> Result<Self> {
+        let mut config = match cli.config.as_deref() {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+
+        if !cli.serial.is_empty() {
+            config.serial = cli.serial.clone();
+        }
+
+        if let Some(level) = cli.log_level {
+            config.log_level = Some(level);
+        }
+
+        if let Some(broker) = cli.mqtt_opt.as_ref() {
+            config.mqtt.host = Some(broker.host.clone());
+            config.mqtt.port = broker.port;
+
+            if let Some(client_id) = broker.client_id.as_ref() {
+                config.mqtt.client_id = client_id.clone();
+            }
+
+            if let Some(transport) = broker.transport {
+                config.mqtt.transport = transport;
+            }
+
+            if let Some(ws_path) = broker.ws_path.as_ref() {
+                config.mqtt.ws_path = ws_path.clone();
+            }
+
+            if let Some(username) = broker.username.as_ref() {
+                config.mqtt.username = Some(username.clone());
+            }
+
+            if let Some(keep_alive) = broker.keep_alive {
+                config.mqtt.keep_alive = keep_alive;
+            }
+
+            config.mqtt.password = broker.password.clone();
+        }
+
+        if let Some(username) = cli.username.as_ref() {
+            config.mqtt.username = Some(username.clone());
+        }
+
+        if let Some(password_file) = cli.password_file.as_ref() {
+            config.mqtt.password_file = Some(password_file.clone());
+        }
+
+        if let Some(password) = cli.password.as_ref() {
+            config.mqtt.password = Some(password.clone());
+        }
+
+        if let Some(topic_prefix) = cli.topic_prefix.as_ref() {
+            config.mqtt.topic_prefix = topic_prefix.clone();
+        }
+
+        if let Some(transport) = cli.transport {
+            config.mqtt.transport = transport;
+        }
+
+        if let Some(ws_path) = cli.ws_path.as_ref() {
+            config.mqtt.ws_path = ws_path.clone();
+        }
+
+        if let Some(ca_file) = cli.ca_file.as_ref() {
+            config.mqtt.tls.ca_file = Some(ca_file.clone());
+        }
+
+        if let Some(client_cert) = cli.client_cert.as_ref() {
+            config.mqtt.tls.client_cert = Some(client_cert.clone());
+        }
+
+        if let Some(client_key) = cli.client_key.as_ref() {
+            config.mqtt.tls.client_key = Some(client_key.clone());
+        }
+
+        if cli.insecure {
+            config.mqtt.tls.insecure = true;
+        }
+
+        if let Some(listen) = cli.http {
+            config.http.listen = Some(listen);
+        }
+
+        if let Some(socket) = cli.control_socket.as_ref() {
+            config.control.enabled = true;
+            config.control.socket = socket.clone();
+        }
+
+        if let Some(discovery_prefix) = cli.discovery_prefix.as_ref() {
+            config.homeassistant.discovery_prefix = discovery_prefix.clone();
+        }
+
+        if cli.homie {
+            config.homie.enabled = true;
+        }
+
+        if let Some(record) = cli.record.as_ref() {
+            config.record = Some(record.clone());
+        }
+
+        if config.mqtt.keep_alive < Duration::from_secs(5) {
+            return Err(anyhow!("MQTT keep alive must be at least 5 seconds"));
+        }
+
+        check_prefix("topic prefix", &config.mqtt.topic_prefix)?;
+        check_prefix("discovery prefix", &config.homeassistant.discovery_prefix)?;
+        check_prefix("Homie base topic", &config.homie.base_topic)?;
+
+        if let Some(Location {
+            latitude,
+            longitude,
+        }) = config.location
+        {
+            if sunrise::Coordinates::new(latitude, longitude).is_none() {
+                return Err(anyhow!("`{latitude}, {longitude}` isn't a valid location"));
+            }
+        }
+
+        check_schedules(&config.schedules, &config.groups, config.location.is_some())?;
+        check_scenes(&config.scenes, &config.groups)?;
+
+        for (serial, dongle) in &config.dongles {
+            if let Some(topic_prefix) = dongle.topic_prefix.as_deref() {
+                check_prefix(&format!("topic prefix for dongle {serial}"), topic_prefix)?;
+            }
+
+            check_schedules(&dongle.schedules, &dongle.groups, config.location.is_some())?;
+            check_scenes(&dongle.scenes, &dongle.groups)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Narrows the configuration down to the dongle reporting `serial`, one of `count` driven.
+    ///
+    /// When several dongles are driven, each gets its own MQTT client, so that it has its own
+    /// availability through its last will, and its topics are moved under its serial number.
+    /// Each of them must then have a section of its own, unless there are no top-level blinds,
+    /// groups, schedules or scenes: those would otherwise apply to every dongle at once.
+    pub fn for_dongle(&self, serial: &str, count: usize) -> Result<Config> {
+        let mut config = self.clone();
+
+        config.dongles = BTreeMap::default();
+
+        let dongle = self.dongles.get(serial);
+
+        match dongle {
+            Some(dongle) => {
+                config.blinds = dongle.blinds.clone();
+                config.groups = dongle.groups.clone();
+                config.schedules = dongle.schedules.clone();
+                config.scenes = dongle.scenes.clone();
+            }
+
+            None if count > 1
+                && !(self.blinds.is_empty()
+                    && self.groups.is_empty()
+                    && self.schedules.is_empty()
+                    && self.scenes.is_empty()) =>
+            {
+                return Err(anyhow!(
+                    "Dongle {serial} has no [dongles.{serial}] section: with {count} dongles \
+                     driven, top-level blinds, groups, schedules and scenes would apply to each"
+                ));
+            }
+
+            None => {}
+        }
+
+        if let Some(topic_prefix) = dongle.and_then(|dongle| dongle.topic_prefix.as_ref()) {
+            config.mqtt.topic_prefix = topic_prefix.clone();
+        } else if count > 1 {
+            config.mqtt.topic_prefix = format!("{}/{}", self.mqtt.topic_prefix, serial);
+        }
+
+        if count > 1 {
+            config.mqtt.client_id = format!("{}-{}", self.mqtt.client_id, serial);
+        }
+
+        Ok(config)
+    }
+
+    pub fn topics(&self) -> Topics {
+        Topics::new(
+            &self.mqtt.topic_prefix,
+            &self.homeassistant.discovery_prefix,
+        )
+    }
+
+    pub fn mqtt_options(&self) -> Result<MqttOptions> {
+        let host = self.mqtt.host.as_deref().ok_or(anyhow!(
+            "No MQTT broker: provide one on the command line or in the config file"
+        ))?;
+
+        let mut options = match self.mqtt.transport {
+            Transport::Mqtt | Transport::Mqtts => {
+                MqttOptions::new(&self.mqtt.client_id, host, self.mqtt.port)
+            }
+
+            // WebSocket transports connect to the broker address as a whole URL.
+            Transport::Ws | Transport::Wss => MqttOptions::new(
+                &self.mqtt.client_id,
+                format!(
+                    "{}://{}:{}{}",
+                    self.mqtt.transport.scheme(),
+                    bracketed(host),
+                    self.mqtt.port,
+                    self.mqtt.ws_path
+                ),
+                self.mqtt.port,
+            ),
+        };
+
+        options.set_keep_alive(self.mqtt.keep_alive);
+
+        options.set_transport(match self.mqtt.transport {
+            Transport::Mqtt => rumqttc::Transport::Tcp,
+            Transport::Mqtts => rumqttc::Transport::tls_with_config(self.tls_configuration()?),
+            Transport::Ws => rumqttc::Transport::Ws,
+            Transport::Wss => rumqttc::Transport::wss_with_config(self.tls_configuration()?),
+        });
+
+        if let Some(username) = self.mqtt.username.as_ref() {
+            options.set_credentials(username, self.mqtt.password()?);
+        }
+
+        Ok(options)
+    }
+
+    fn tls_configuration(&self) -> Result<TlsConfiguration> {
+        let config = tls::client_config(&self.mqtt.tls)?;
+
+        Ok(TlsConfiguration::Rustls(Arc::new(config)))
+    }
+
+    pub fn blind(&self, id: u8) -> Option<&BlindConfig> {
+        self.blinds.get(&id)
+    }
+
+    /// The actions of scene `name`, if there is one.
+    pub fn scene(&self, name: &str) -> Option<Vec<Action>> {
+        self.scenes.get(name)?.actions(&self.groups).ok()
+    }
+
+    pub fn group(&self, name: &str) -> Option<&[u8]> {
+        self.groups.get(name).map(Vec::as_slice)
+    }
+
+    /// Prints a summary of the effective configuration, for `--check-config`.
+    pub fn print_summary(&self) -> Result<()> {
+        let options = self.mqtt_options()?;
+        let (host, port) = options.broker_address();
+
+        println!("Configuration is valid.");
+        println!(
+            "Serial ports: {}",
+            match self.serial.as_slice() {
+                [] => "auto-detect".to_string(),
+                ports => ports.join(", "),
+            }
+        );
+        println!(
+            "Log level: {}",
+            self.log_level.unwrap_or(LevelFilter::Debug)
+        );
+        println!(
+            "MQTT broker: {} as '{}' ({})",
+            match self.mqtt.transport {
+                // The address already is a whole URL.
+                Transport::Ws | Transport::Wss => host,
+                Transport::Mqtt | Transport::Mqtts => format!("{}:{}", bracketed(&host), port),
+            },
+            options.client_id(),
+            match self.mqtt.username.as_deref() {
+                Some(username) => format!("user '{username}'"),
+                None => "anonymous".to_string(),
+            }
+        );
+        println!(
+            "RSSI polled every {}",
+            humantime::format_duration(self.rssi_interval)
+        );
+        println!(
+            "Frames sent at least {} apart",
+            humantime::format_duration(self.frame_spacing)
+        );
+
+        if let Some(record) = self.record.as_deref() {
+            println!(
+                "Traffic with the dongles recorded in '{}'",
+                record.display()
+            );
+        }
+        println!(
+            "MQTT transport: {}, keep alive {}",
+            self.mqtt.transport.scheme(),
+            humantime::format_duration(self.mqtt.keep_alive)
+        );
+
+        if matches!(self.mqtt.transport, Transport::Mqtts | Transport::Wss) {
+            let TlsConfig {
+                ca_file,
+                client_cert,
+                insecure,
+                ..
+            } = &self.mqtt.tls;
+
+            println!(
+                "TLS: checking broker against {}{}",
+                match ca_file.as_deref() {
+                    Some(path) => format!("'{}'", path.display()),
+                    None => "system certificates".to_string(),
+                },
+                if *insecure { " (disabled)" } else { "" }
+            );
+
+            if let Some(path) = client_cert.as_deref() {
+                println!("TLS: client certificate '{}'", path.display());
+            }
+        }
+
+        if let Some(listen) = self.http.listen {
+            println!("HTTP API on {listen}");
+        }
+
+        if self.control.enabled {
+            println!("Control socket: '{}'", self.control.socket.display());
+        }
+
+        println!("Topic prefix: {}", self.mqtt.topic_prefix);
+        println!("Discovery prefix: {}", self.homeassistant.discovery_prefix);
+
+        if self.homie.enabled {
+            println!("Homie base topic: {}", self.homie.base_topic);
+        }
+
+        if let Some(Location {
+            latitude,
+            longitude,
+        }) = self.location
+        {
+            println!("Location: {latitude}, {longitude}");
+        }
+
+        print_slots(&self.blinds, &self.groups, "");
+        print_schedules(&self.schedules, "");
+        print_scenes(&self.scenes, "");
+
+        for (serial, dongle) in &self.dongles {
+            match dongle.topic_prefix.as_deref() {
+                Some(topic_prefix) => println!("Dongle {serial}: topic prefix {topic_prefix}"),
+                None => println!("Dongle {serial}:"),
+            }
+
+            print_slots(&dongle.blinds, &dongle.groups, "  ");
+            print_schedules(&dongle.schedules, "  ");
+            print_scenes(&dongle.scenes, "  ");
+        }
+
+        Ok(())
+    }
+}
+
+fn print_slots(
+    blinds: &BTreeMap<u8, BlindConfig>,
+    groups: &BTreeMap<String, Vec<u8>>,
+    indent: &str,
+) {
+    for (id, blind) in blinds {
+        print!(
+            "{}Blind {}: '{}' ({})",
+            indent,
+            id,
+            blind.name.as_deref().unwrap_or("unnamed"),
+            blind.device_class.as_str()
+        );
+
+        if let Some(area) = blind.area.as_deref() {
+            print!(", in '{area}'");
+        }
+
+        if let Some(TravelTime { open, close }) = blind.travel_time.as_ref() {
+            print!(
+                ", opens in {}, closes in {}",
+                humantime::format_duration(*open),
+                humantime::format_duration(*close)
+            );
+        }
+
+        if let Some(TravelTime { open, close }) = blind.tilt_time.as_ref() {
+            print!(
+                ", tilts open in {}, closed in {}",
+                humantime::format_duration(*open),
+                humantime::format_duration(*close)
+            );
+        }
+
+        match blind.my_position {
+            Some(position) => println!(", MY at {position}%"),
+            None => println!(),
+        }
+    }
+
+    for (name, members) in groups {
+        println!("{}Group {}: blinds {:?}", indent, name, members);
+    }
+}
+
+fn print_scenes(scenes: &BTreeMap<String, Scene>, indent: &str) {
+    for (name, scene) in scenes {
+        println!("{}Scene {}: {} steps", indent, name, scene.steps.len());
+    }
+}
+
+fn print_schedules(schedules: &BTreeMap<String, ScheduleConfig>, indent: &str) {
+    for (name, schedule) in schedules {
+        print!("{}Schedule {}: {}", indent, name, schedule.command.as_str());
+
+        match (schedule.cron.as_deref(), schedule.sun) {
+            (Some(cron), _) => print!(" on '{cron}'"),
+            (None, Some(sun)) => {
+                print!(" at {} {:+}s", sun.as_str(), schedule.offset.num_seconds())
+            }
+            (None, None) => {}
+        }
+
+        print!(
+            ", blinds {:?}, groups {:?}",
+            schedule.blinds, schedule.groups
+        );
+
+        if !schedule.enabled {
+            print!(" (disabled)");
+        }
+
+        println!();
+    }
+}
+
+impl MqttConfig {
+    fn password(&self) -> Result<String> {
+        if let Some(password) = self.password.as_ref() {
+            return Ok(password.clone());
+        }
+
+        match self.password_file.as_deref() {
+            Some(path) => fs::read_to_string(path)
+                .map(|password| password.trim_end_matches(['\r', '\n']).to_string())
+                .with_context(|| format!("Failed to read password file '{}'", path.display())),
+
+            None => Ok(String::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use clap::Parser;
+
+    use super::*;
+
+    fn parse(config: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(config)
+    }
+
+    /// Runs the bridge's command line, `{config}` standing for a file holding `config`.
+    fn from_cli(name: &str, config: &str, args: &[&str]) -> Result<Config> {
+        let path = env::temp_dir().join(format!("somfy-rts-mqtt-{}-{name}.toml", process::id()));
+        fs::write(&path, config).unwrap();
+
+        let args = args.iter().map(|arg| match *arg {
+            "{config}" => path.to_str().unwrap(),
+            arg => arg,
+        });
+
+        let config = Config::from_cli(&Cli::parse_from(["somfy-rts-mqtt"].into_iter().chain(args)));
+
+        fs::remove_file(&path).unwrap();
+
+        config
+    }
+
+    #[test]
+    fn command_line_overrides_file() {
+        let config = from_cli(
+            "overrides",
+            r#"
+                [mqtt]
+                host = "file-host"
+                client_id = "file-id"
+                topic_prefix = "file-prefix"
+                keep_alive = "30s"
+            "#,
+            &[
+                "--config",
+                "{config}",
+                "--topic-prefix",
+                "cli/prefix",
+                "mqtt://cli-host:1884/?keepalive=10",
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.mqtt.host.as_deref(), Some("cli-host"));
+        assert_eq!(config.mqtt.port, 1884);
+        assert_eq!(config.mqtt.client_id, "file-id");
+        assert_eq!(config.mqtt.topic_prefix, "cli/prefix");
+        assert_eq!(config.mqtt.keep_alive, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn file_is_used_where_command_line_is_silent() {
+        let config = from_cli(
+            "file",
+            r#"
+                [mqtt]
+                topic_prefix = "upstairs"
+
+                [homeassistant]
+                discovery_prefix = "ha"
+            "#,
+            &["--config", "{config}", "--homie"],
+        )
+        .unwrap();
+
+        assert_eq!(config.mqtt.topic_prefix, "upstairs");
+        assert_eq!(config.homeassistant.discovery_prefix, "ha");
+        assert!(config.homie.enabled);
+    }
+
+    #[test]
+    fn short_keep_alive_is_refused() {
+        let error = from_cli(
+            "keep-alive",
+            r#"
+                [mqtt]
+                keep_alive = "4s"
+            "#,
+            &["--config", "{config}"],
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "MQTT keep alive must be at least 5 seconds"
+        );
+
+        assert!(from_cli(
+            "keep-alive-5s",
+            r#"
+                [mqtt]
+                keep_alive = "5s"
+            "#,
+            &["--config", "{config}"],
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn bad_prefix_from_file_is_refused() {
+        let error = from_cli(
+            "prefix",
+            r#"
+                [homeassistant]
+                discovery_prefix = "ha/"
+            "#,
+            &["--config", "{config}"],
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("discovery prefix"));
+    }
+
+    #[test]
+    fn prefixes_are_checked() {
+        for prefix in ["somfy-rts", "somfy-rts/upstairs", "a"] {
+            assert!(check_prefix("topic prefix", prefix).is_ok(), "{prefix}");
+        }
+
+        for prefix in ["", "/somfy-rts", "somfy-rts/", "somfy/+", "somfy/#"] {
+            assert!(check_prefix("topic prefix", prefix).is_err(), "{prefix}");
+        }
+    }
+
+    #[test]
+    fn slots_are_bounded() {
+        let config = parse(
+            r#"
+                [blinds.1]
+                name = "Kitchen"
+
+                [blinds.100]
+                my_position = 100
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.blinds.keys().copied().collect::<Vec<_>>(), [1, 100]);
+
+        for slot in ["0", "101", "kitchen"] {
+            let error = parse(&format!("[blinds.{slot}]\n")).unwrap_err();
+
+            assert!(
+                error
+                    .message()
+                    .contains(&format!("`{slot}` isn't a valid blind id")),
+                "{error}"
+            );
+        }
+
+        let error = parse("[blinds.3]\nmy_position = 101\n").unwrap_err();
+
+        assert!(error
+            .message()
+            .contains("`101` isn't a valid MY position for blind 3"));
+    }
+
+    #[test]
+    fn unknown_keys_are_refused() {
+        assert!(parse("[mqtt]\nhots = \"broker\"\n").is_err());
+        assert!(parse("[blinds.1]\ntravel = \"20s\"\n").is_err());
+    }
+
+    #[test]
+    fn single_dongle_keeps_top_level_settings() {
+        let config = parse(
+            r#"
+                [blinds.1]
+                name = "Kitchen"
+            "#,
+        )
+        .unwrap();
+
+        let dongle = config.for_dongle("0123", 1).unwrap();
+
+        assert!(dongle.blind(1).is_some());
+        assert_eq!(dongle.mqtt.topic_prefix, "somfy-rts");
+        assert_eq!(dongle.mqtt.client_id, "somfy-rts-mqtt");
+    }
+
+    #[test]
+    fn several_dongles_need_their_own_section() {
+        let config = parse(
+            r#"
+                [blinds.1]
+                name = "Kitchen"
+            "#,
+        )
+        .unwrap();
+
+        let error = config.for_dongle("0123", 2).unwrap_err();
+
+        assert!(error
+            .to_string()
+            .starts_with("Dongle 0123 has no [dongles.0123] section"));
+    }
+
+    #[test]
+    fn several_dongles_are_set_apart() {
+        let config = parse(
+            r#"
+                [dongles.0123]
+                topic_prefix = "somfy-rts/upstairs"
+
+                [dongles.0123.blinds.1]
+                name = "Bedroom"
+
+                [dongles.4567.blinds.2]
+                name = "Kitchen"
+            "#,
+        )
+        .unwrap();
+
+        let upstairs = config.for_dongle("0123", 2).unwrap();
+
+        assert_eq!(upstairs.mqtt.topic_prefix, "somfy-rts/upstairs");
+        assert_eq!(upstairs.mqtt.client_id, "somfy-rts-mqtt-0123");
+        assert_eq!(upstairs.blinds.keys().copied().collect::<Vec<_>>(), [1]);
+        assert!(upstairs.dongles.is_empty());
+
+        let downstairs = config.for_dongle("4567", 2).unwrap();
+
+        assert_eq!(downstairs.mqtt.topic_prefix, "somfy-rts/4567");
+        assert_eq!(downstairs.blinds.keys().copied().collect::<Vec<_>>(), [2]);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    fs::{self, Permissions},
+    io::ErrorKind,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use somfy_rts::rpc::{self, Answer, Call};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    time,
+};
+
+use crate::{
+    http::Dongle,
+    queue::{CommandQueue, Failure, Outcome, Reply, Request},
+};
+
+/// Pause after a failed accept, doubled on each failure in a row, such as when running out of
+/// file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Listens on the control socket, taking over the one a previous run may have left behind.
+///
+/// Whoever can connect drives the dongles: only the user running the bridge may.
+pub async fn listen(path: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(path).await.is_ok() {
+        bail!("Another process listens on '{}'", path.display());
+    }
+
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Failed to remove '{}'", path.display()))
+        }
+        _ => {}
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create '{}'", dir.display()))?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to listen on '{}'", path.display()))?;
+
+    fs::set_permissions(path, Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict access to '{}'", path.display()))?;
+
+    info!(target: "control", "Serving control socket at '{}'.", path.display());
+
+    Ok(listener)
+}
+
+/// Answers the calls of `somfy-rts-cli`, which go through the same queues as MQTT commands.
+pub async fn serve(listener: UnixListener, dongles: Vec<Dongle>) -> Result<()> {
+    let dongles = Arc::new(dongles);
+    let mut backoff = ACCEPT_BACKOFF;
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                backoff = ACCEPT_BACKOFF;
+                tokio::spawn(serve_client(stream, dongles.clone()));
+            }
+
+            Err(e) => {
+                warn!(
+                    target: "control",
+                    "Failed to accept a client: {}, retrying in {}.",
+                    e,
+                    humantime::format_duration(backoff)
+                );
+
+                time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+            }
+        }
+    }
+}
+
+async fn serve_client(stream: UnixStream, dongles: Arc<Vec<Dongle>>) {
+    let (reader, mut writer) = stream.into_split();
+
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let answer = match serde_json::from_str::<rpc::Request>(&line) {
+            Ok(request) => call(&dongles, request).await,
+
+            Err(e) => {
+                warn!(target: "control", "Received invalid request: {}", e);
+                error(format!("Invalid request: {}", e))
+            }
+        };
+
+        let mut line = serde_json::to_string(&answer).unwrap();
+        line.push('\n');
+
+        if writer.write_all(line.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn error(message: String) -> Answer {
+    Answer::Error { message }
+}
+
+async fn execute(queue: &CommandQueue, request: Request) -> Outcome {
+    queue
+        .push(request)
+        .await
+        .unwrap_or_else(|_| Err(Failure::Error("Lost the dongle".to_string())))
+}
+
+async fn call(dongles: &[Dongle], request: rpc::Request) -> Answer {
+    let dongle = match (request.dongle.as_deref(), dongles) {
+        (None, [dongle]) => dongle,
+
+        (None, _) => return error("Several dongles are driven, tell which one".to_string()),
+
+        (Some(serial), dongles) => match dongles.iter().find(|dongle| dongle.serial == serial) {
+            Some(dongle) => dongle,
+            None => return error(format!("No dongle {}", serial)),
+        },
+    };
+
+    debug!(target: "control", "Received {:?} for dongle {}", request.call, dongle.serial);
+
+    if let Call::Operate { blind, .. }
+    | Call::Prog { blind }
+    | Call::GetAddress { blind }
+    | Call::ResetAddress { blind } = request.call
+    {
+        if !(1..=100).contains(&blind) {
+            return error(format!("No slot {}, expected 1 to 100", blind));
+        }
+    }
+
+    let outcome = match request.call {
+        Call::Info => {
+            return Answer::Info {
+                serial: dongle.serial.clone(),
+                firmware: dongle.firmware.clone(),
+            }
+        }
+
+        Call::Operate { blind, order } => {
+            execute(&dongle.queue, Request::Operate(order.rts(blind))).await
+        }
+
+        // Pairing through the bridge announces the blind right away.
+        Call::Prog { blind } => execute(&dongle.queue, Request::Pair(blind)).await,
+
+        Call::GetAddress { blind } => execute(&dongle.queue, Request::GetBlind(blind)).await,
+
+        Call::ResetAddress { blind } => execute(&dongle.queue, Request::Unpair(blind)).await,
+
+        Call::RunScene { name } => match dongle.scenes.get(&name) {
+            Some(actions) => dongle.queue.run_scene(actions).await.map(|_| Reply::Done),
+            None => return error(format!("No scene {}", name)),
+        },
+    };
+
+    match outcome {
+        Ok(Reply::Blind(blind)) => Answer::Blind(blind),
+        Ok(_) => Answer::Done,
+        Err(Failure::Refused(message)) => Answer::Refused { message },
+        Err(failure) => error(failure.to_string()),
+    }
+}
@@ -0,0 +1,402 @@
+use std::time::{Duration, Instant};
+
+use crate::config::TravelTime;
+
+pub const OPEN: f64 = 100.0;
+pub const CLOSED: f64 = 0.0;
+
+/// Estimates where a blind stands, RTS objects being unable to tell it themselves.
+///
+/// Positions go from 0 (closed) to 100 (open) and are computed from the travel times
+/// of the blind, assuming it moves at a constant speed.
+pub struct Cover {
+    open_time: Duration,
+    close_time: Duration,
+    position: Option<f64>,
+    motion: Option<Motion>,
+}
+
+struct Motion {
+    from: Option<f64>,
+    to: f64,
+    started: Instant,
+}
+
+impl Cover {
+    pub fn new(travel_time: &TravelTime) -> Self {
+        Cover {
+            open_time: travel_time.open,
+            close_time: travel_time.close,
+            position: None,
+            motion: None,
+        }
+    }
+
+    /// Estimated position at `now`, if it can be known at all.
+    pub fn position(&self, now: Instant) -> Option<f64> {
+        let Some(motion) = self.motion.as_ref() else {
+            return self.position;
+        };
+
+        let elapsed = now.saturating_duration_since(motion.started);
+
+        match motion.from {
+            Some(from) => {
+                let full_course = if motion.to >= from {
+                    self.open_time
+                } else {
+                    self.close_time
+                };
+
+                let travelled = OPEN * elapsed.as_secs_f64() / full_course.as_secs_f64();
+
+                Some(if motion.to >= from {
+                    (from + travelled).min(motion.to)
+                } else {
+                    (from - travelled).max(motion.to)
+                })
+            }
+
+            // Starting from nowhere, we only know where we are once a full course is done.
+            None => (elapsed >= self.open_time.max(self.close_time)).then_some(motion.to),
+        }
+    }
+
+    /// Where the blind will stand once it is done moving.
+    pub fn destination(&self, now: Instant) -> Option<f64> {
+        match self.motion.as_ref() {
+            Some(motion) => Some(motion.to),
+            None => self.position(now),
+        }
+    }
+
+    pub fn is_moving(&self, now: Instant) -> bool {
+        match self.motion.as_ref() {
+            Some(motion) => self.position(now) != Some(motion.to),
+            None => false,
+        }
+    }
+
+    pub fn move_to(&mut self, target: f64, now: Instant) {
+        self.motion = Some(Motion {
+            from: self.position(now),
+            to: target.clamp(CLOSED, OPEN),
+            started: now,
+        });
+    }
+
+    pub fn stop(&mut self, now: Instant) {
+        self.position = self.position(now);
+        self.motion = None;
+    }
+
+    /// MY stops a moving blind, like STOP does, and otherwise sends it to `my_position`.
+    ///
+    /// Returns whether the blind was affected, which it is not when standing still with no
+    /// known MY position.
+    pub fn my(&mut self, my_position: Option<f64>, now: Instant) -> bool {
+        if self.is_moving(now) {
+            self.stop(now);
+        } else if let Some(position) = my_position {
+            self.move_to(position, now);
+        } else {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Estimates the slats' angle of a venetian blind, tilted by short UP/DOWN presses.
+///
+/// Angles go from 0 (closed) to 100 (open).
+pub struct Tilt {
+    open_time: Duration,
+    close_time: Duration,
+    angle: Option<f64>,
+}
+
+/// A press on UP or DOWN, to be followed by STOP once `duration` has elapsed.
+#[derive(Debug, Clone)]
+pub struct Pulse {
+    pub opening: bool,
+    pub duration: Duration,
+}
+
+impl Tilt {
+    pub fn new(tilt_time: &TravelTime) -> Self {
+        Tilt {
+            open_time: tilt_time.open,
+            close_time: tilt_time.close,
+            angle: None,
+        }
+    }
+
+    pub fn angle(&self) -> Option<f64> {
+        self.angle
+    }
+
+    /// Pulses bringing the slats from their current angle to `target`, which only becomes
+    /// the current angle once they went through.
+    ///
+    /// When the current angle is unknown, the slats are first fully closed.
+    pub fn pulses_to(&self, target: f64) -> Vec<Pulse> {
+        let target = target.clamp(CLOSED, OPEN);
+
+        let (from, mut pulses) = match self.angle {
+            Some(angle) => (angle, Vec::new()),
+
+            None if target == OPEN => (CLOSED, Vec::new()),
+
+            None => (
+                CLOSED,
+                vec![Pulse {
+                    opening: false,
+                    duration: self.close_time,
+                }],
+            ),
+        };
+
+        if target > from {
+            pulses.push(Pulse {
+                opening: true,
+                duration: self.open_time.mul_f64((target - from) / OPEN),
+            });
+        } else if target < from {
+            pulses.push(Pulse {
+                opening: false,
+                duration: self.close_time.mul_f64((from - target) / OPEN),
+            });
+        }
+
+        pulses
+    }
+
+    /// The pulses bringing the slats to `angle` went through.
+    pub fn reached(&mut self, angle: f64) {
+        self.angle = Some(angle.clamp(CLOSED, OPEN));
+    }
+
+    /// Pulses were cut short: the slats could be anywhere.
+    pub fn lost(&mut self) {
+        self.angle = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    /// Opens in 20 seconds, closes in 10.
+    fn cover() -> Cover {
+        Cover::new(&TravelTime {
+            open: 20 * SECOND,
+            close: 10 * SECOND,
+        })
+    }
+
+    fn cover_at(position: f64) -> Cover {
+        Cover {
+            position: Some(position),
+            ..cover()
+        }
+    }
+
+    #[test]
+    fn position_follows_travel_times() {
+        let start = Instant::now();
+        let mut cover = cover_at(CLOSED);
+
+        cover.move_to(OPEN, start);
+
+        assert_eq!(cover.position(start + 5 * SECOND), Some(25.0));
+        assert_eq!(cover.position(start + 20 * SECOND), Some(OPEN));
+        assert_eq!(cover.position(start + 30 * SECOND), Some(OPEN));
+        assert!(!cover.is_moving(start + 20 * SECOND));
+
+        let start = start + 30 * SECOND;
+        cover.move_to(CLOSED, start);
+
+        assert_eq!(cover.destination(start), Some(CLOSED));
+        assert_eq!(cover.position(start + 5 * SECOND), Some(50.0));
+        assert_eq!(cover.position(start + 10 * SECOND), Some(CLOSED));
+    }
+
+    #[test]
+    fn unknown_position_is_known_after_a_full_course() {
+        let start = Instant::now();
+        let mut cover = cover();
+
+        assert_eq!(cover.position(start), None);
+
+        // Closing takes 10 seconds, but the blind may have been open as well as anywhere.
+        cover.move_to(CLOSED, start);
+
+        assert_eq!(cover.position(start + 10 * SECOND), None);
+        assert!(cover.is_moving(start + 10 * SECOND));
+        assert_eq!(cover.position(start + 20 * SECOND), Some(CLOSED));
+    }
+
+    #[test]
+    fn stop_holds_position_mid_travel() {
+        let start = Instant::now();
+        let mut cover = cover_at(CLOSED);
+
+        cover.move_to(OPEN, start);
+        cover.stop(start + 10 * SECOND);
+
+        assert!(!cover.is_moving(start + 10 * SECOND));
+        assert_eq!(cover.position(start + 30 * SECOND), Some(50.0));
+        assert_eq!(cover.destination(start + 30 * SECOND), Some(50.0));
+    }
+
+    #[test]
+    fn stop_mid_travel_from_unknown_position_is_unknown() {
+        let start = Instant::now();
+        let mut cover = cover();
+
+        cover.move_to(OPEN, start);
+        cover.stop(start + 10 * SECOND);
+
+        assert_eq!(cover.position(start + 30 * SECOND), None);
+    }
+
+    #[test]
+    fn my_stops_moving_blind() {
+        let start = Instant::now();
+        let mut cover = cover_at(OPEN);
+
+        cover.move_to(CLOSED, start);
+
+        assert!(cover.my(Some(40.0), start + 2 * SECOND));
+        assert!(!cover.is_moving(start + 2 * SECOND));
+        assert_eq!(cover.destination(start + 2 * SECOND), Some(80.0));
+    }
+
+    #[test]
+    fn my_goes_to_my_position() {
+        let start = Instant::now();
+        let mut cover = cover_at(CLOSED);
+
+        assert!(cover.my(Some(40.0), start));
+        assert_eq!(cover.destination(start), Some(40.0));
+        assert_eq!(cover.position(start + 4 * SECOND), Some(20.0));
+        assert_eq!(cover.position(start + 8 * SECOND), Some(40.0));
+    }
+
+    #[test]
+    fn my_without_my_position_does_nothing() {
+        let start = Instant::now();
+        let mut cover = cover_at(60.0);
+
+        assert!(!cover.my(None, start));
+        assert!(!cover.is_moving(start));
+        assert_eq!(cover.position(start), Some(60.0));
+    }
+
+    /// Opens in 1 second, closes in 800 milliseconds.
+    fn tilt_at(angle: Option<f64>) -> Tilt {
+        Tilt {
+            angle,
+            ..Tilt::new(&TravelTime {
+                open: SECOND,
+                close: Duration::from_millis(800),
+            })
+        }
+    }
+
+    fn pulses(pulses: Vec<Pulse>) -> Vec<(bool, Duration)> {
+        pulses
+            .into_iter()
+            .map(|pulse| (pulse.opening, pulse.duration))
+            .collect()
+    }
+
+    #[test]
+    fn tilting_from_known_angle() {
+        let tilt = tilt_at(Some(25.0));
+
+        assert_eq!(
+            pulses(tilt.pulses_to(75.0)),
+            [(true, Duration::from_millis(500))]
+        );
+        assert_eq!(
+            pulses(tilt.pulses_to(CLOSED)),
+            [(false, Duration::from_millis(200))]
+        );
+        assert!(tilt.pulses_to(25.0).is_empty());
+    }
+
+    #[test]
+    fn tilting_to_either_end() {
+        assert_eq!(
+            pulses(tilt_at(Some(CLOSED)).pulses_to(OPEN)),
+            [(true, SECOND)]
+        );
+        assert_eq!(
+            pulses(tilt_at(Some(OPEN)).pulses_to(CLOSED)),
+            [(false, Duration::from_millis(800))]
+        );
+
+        // Out of range angles are brought back to the ends.
+        assert_eq!(
+            pulses(tilt_at(Some(CLOSED)).pulses_to(120.0)),
+            [(true, SECOND)]
+        );
+        assert!(tilt_at(Some(CLOSED)).pulses_to(-5.0).is_empty());
+    }
+
+    #[test]
+    fn tilting_from_unknown_angle_closes_first() {
+        let tilt = tilt_at(None);
+
+        assert_eq!(
+            pulses(tilt.pulses_to(CLOSED)),
+            [(false, Duration::from_millis(800))]
+        );
+        assert_eq!(
+            pulses(tilt.pulses_to(40.0)),
+            [
+                (false, Duration::from_millis(800)),
+                (true, Duration::from_millis(400))
+            ]
+        );
+
+        // Opening fully needs no reference point.
+        assert_eq!(pulses(tilt.pulses_to(OPEN)), [(true, SECOND)]);
+    }
+
+    #[test]
+    fn fractional_angles_give_proportional_pulses() {
+        let tilt = tilt_at(Some(CLOSED));
+
+        let [(true, duration)] = pulses(tilt.pulses_to(12.5))[..] else {
+            panic!("expected a single opening pulse");
+        };
+
+        assert_eq!(duration.as_micros(), 125_000);
+
+        // The slats move even for less than an angle's worth.
+        let [(false, duration)] = pulses(tilt_at(Some(50.0)).pulses_to(49.6))[..] else {
+            panic!("expected a single closing pulse");
+        };
+
+        assert_eq!(duration.as_micros(), 3_200);
+    }
+
+    #[test]
+    fn angle_is_kept_once_reached() {
+        let mut tilt = tilt_at(None);
+
+        tilt.reached(60.0);
+        assert_eq!(tilt.angle(), Some(60.0));
+
+        tilt.reached(150.0);
+        assert_eq!(tilt.angle(), Some(OPEN));
+
+        tilt.lost();
+        assert_eq!(tilt.angle(), None);
+    }
+}
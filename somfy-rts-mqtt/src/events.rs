@@ -0,0 +1,115 @@
+use std::{sync::Arc, time::SystemTime};
+
+use serde::Serialize;
+use serde_json::Value;
+use somfy_rts::{Exchange, Observer};
+use tokio::sync::broadcast;
+
+use crate::metrics;
+
+/// Events kept for subscribers lagging behind, before they start missing some.
+const CAPACITY: usize = 256;
+
+/// Something that happened to a dongle.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Event {
+    /// A command went through the dongle.
+    Command {
+        dongle: String,
+        command: Value,
+        blind: Option<u8>,
+        /// `DONGLE_OK` or `DONGLE_KO`, if the dongle answered at all.
+        ack: Option<String>,
+        rolling_code: Option<Value>,
+        latency_ms: u64,
+        error: Option<String>,
+        timestamp: String,
+    },
+
+    /// The dongle went online, or away.
+    Connection {
+        dongle: String,
+        state: Connection,
+        timestamp: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Connection {
+    Online,
+    Rebooting,
+    Offline,
+}
+
+/// Every dongle's events, as they happen.
+#[derive(Clone)]
+pub struct Events {
+    sender: broadcast::Sender<Event>,
+}
+
+fn now() -> String {
+    humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+}
+
+impl Event {
+    fn command(dongle: &str, exchange: &Exchange) -> Self {
+        let (ack, rolling_code, error) = match &exchange.answer {
+            Ok(answer) => (
+                answer["ACK"].as_str().map(str::to_string),
+                answer
+                    .get("ADDRESS-VAL")
+                    .and_then(|address_val| address_val.get(2))
+                    .cloned(),
+                answer["ERROR"].as_str().map(str::to_string),
+            ),
+
+            Err(e) => (None, None, Some(e.clone())),
+        };
+
+        Event::Command {
+            dongle: dongle.to_string(),
+            command: exchange.command.clone(),
+            blind: exchange.blind,
+            ack,
+            rolling_code,
+            latency_ms: exchange.latency.as_millis() as u64,
+            error,
+            timestamp: now(),
+        }
+    }
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+
+        Events { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    pub fn connection(&self, dongle: &str, state: Connection) {
+        let _ = self.sender.send(Event::Connection {
+            dongle: dongle.to_string(),
+            state,
+            timestamp: now(),
+        });
+    }
+
+    /// Turns every command sent to dongle `serial` into an event, counting it in the metrics
+    /// on the way: subscribers lagging behind may miss events.
+    pub fn observer(&self, serial: &str) -> Observer {
+        let sender = self.sender.clone();
+        let serial = serial.to_string();
+
+        Arc::new(move |exchange| {
+            metrics::command(&serial, exchange);
+
+            let _ = sender.send(Event::command(&serial, exchange));
+        })
+    }
+}
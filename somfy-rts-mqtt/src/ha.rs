@@ -1,16 +1,36 @@
+use std::{collections::BTreeMap, time::SystemTime};
+
 use anyhow::Result;
-use rumqttc::{AsyncClient, QoS};
-use serde_json::json;
+use chrono::SecondsFormat;
+use rumqttc::{AsyncClient, LastWill, QoS};
+use serde_json::{json, Value};
 use somfy_rts::AddressVal;
 
-const HA_DISCOVERY_PREFIX: &str = "homeassistant";
+use crate::{
+    command::Order,
+    config::Config,
+    queue::{Failure, Outcome, Reply},
+    schedule::Rule,
+    somfy::DongleInfo,
+    topics::Topics,
+};
+
 const HA_MQTT_COMPONENT: &str = "cover";
-const HA_MQTT_NODEID: &str = "somfy-rts";
+
+/// Buttons announced for the dongle itself, next to the per-blind MY buttons.
+const DONGLE_BUTTONS: [&str; 2] = ["pair", "reboot"];
+
+/// Sensors announced for the dongle itself, next to the next run of each schedule.
+const DONGLE_SENSORS: [&str; 4] = ["rssi", "last_result", "errors", "queue"];
+
+/// Orders for the dongle itself, each under `<base>/dongle/`.
+const DONGLE_COMMANDS: [&str; 3] = ["reboot", "pair", "unpair/+"];
 
 pub async fn setup_mqtt_autodiscovery<'a>(
     client: &'a AsyncClient,
     blinds: &[(u8, AddressVal)],
     dongle_id: &str,
+    config: &Config,
 ) -> Result<&'a AsyncClient> {
     for (
         id,
@@ -21,54 +41,744 @@ pub async fn setup_mqtt_autodiscovery<'a>(
     {
         let addr = addr.as_str().unwrap();
 
-        let command_topic = format!("{}/cover/{}/set", HA_MQTT_NODEID, id);
-        let config_topic = format!(
-            "{HA_DISCOVERY_PREFIX}/{HA_MQTT_COMPONENT}/{}/{}/config",
-            dongle_id, addr
-        );
+        announce_blind(client, *id, addr, dongle_id, config).await?;
+    }
+
+    Ok(client)
+}
+
+/// Subscribes to the command topics of a blind and publishes its discovery config.
+pub async fn announce_blind(
+    client: &AsyncClient,
+    id: u8,
+    addr: &str,
+    dongle_id: &str,
+    config: &Config,
+) -> Result<()> {
+    let topics = config.topics();
+
+    let config_topic = topics.discovery_config(HA_MQTT_COMPONENT, dongle_id, addr);
+
+    let blind_config = config_for_blind(id, dongle_id, addr, config);
+    let config_payload = serde_json::to_string(&blind_config).unwrap();
+
+    let my_button_topic = topics.discovery_config("button", dongle_id, addr);
+    let my_button_payload =
+        serde_json::to_string(&my_button_for_blind(id, dongle_id, addr, config)).unwrap();
+
+    subscribe_blind(client, &topics, id, config).await?;
 
-        let config = config_for_blind(*id, dongle_id, addr);
-        let config_payload = serde_json::to_string(&config).unwrap();
+    client
+        .publish(config_topic, QoS::AtLeastOnce, true, config_payload)
+        .await?;
 
+    client
+        .publish(my_button_topic, QoS::AtLeastOnce, true, my_button_payload)
+        .await?;
+
+    Ok(())
+}
+
+async fn subscribe_blind(
+    client: &AsyncClient,
+    topics: &Topics,
+    id: u8,
+    config: &Config,
+) -> Result<()> {
+    client.subscribe(topics.cover(id, "set"), QoS::AtMostOnce).await?;
+
+    if config
+        .blind(id)
+        .and_then(|blind| blind.tilt_time.as_ref())
+        .is_some()
+    {
+        client
+            .subscribe(topics.cover(id, "tilt"), QoS::AtMostOnce)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Subscribes again to every topic the bridge listens on, with `blinds` the slots in use, once
+/// it connected to the broker with a new session.
+pub async fn resubscribe(
+    client: &AsyncClient,
+    blinds: &[u8],
+    dongle_id: &str,
+    config: &Config,
+) -> Result<()> {
+    let topics = config.topics();
+
+    for id in blinds {
+        subscribe_blind(client, &topics, *id, config).await?;
+    }
+
+    let command_topics = DONGLE_COMMANDS
+        .iter()
+        .map(|leaf| topics.dongle(leaf))
+        .chain(config.groups.keys().map(|name| topics.group(name, "set")))
+        .chain(config.schedules.keys().map(|name| topics.schedule(name, "set")))
+        .chain(config.scenes.keys().map(|name| topics.scene(name, "set")));
+
+    for command_topic in command_topics {
         client.subscribe(command_topic, QoS::AtMostOnce).await?;
+    }
+
+    watch_discovery_configs(client, dongle_id, config).await?;
+
+    Ok(())
+}
+
+/// Unsubscribes from the command topics of a blind and removes its entities from Home Assistant.
+pub async fn withdraw_blind(
+    client: &AsyncClient,
+    id: u8,
+    addr: &str,
+    dongle_id: &str,
+    config: &Config,
+) -> Result<()> {
+    let topics = config.topics();
+
+    client.unsubscribe(topics.cover(id, "set")).await?;
+
+    client.unsubscribe(topics.cover(id, "tilt")).await?;
+
+    for component in [HA_MQTT_COMPONENT, "button"] {
+        let config_topic = topics.discovery_config(component, dongle_id, addr);
+
+        delete_config(client, &config_topic).await?;
+    }
+
+    Ok(())
+}
+
+/// Subscribes to the covers and buttons announced for the dongle, so that retained configs
+/// left over by a previous run can be checked against the current slots.
+pub async fn watch_discovery_configs<'a>(
+    client: &'a AsyncClient,
+    dongle_id: &str,
+    config: &Config,
+) -> Result<&'a AsyncClient> {
+    let topics = config.topics();
+
+    for component in [HA_MQTT_COMPONENT, "button", "switch", "sensor", "scene"] {
+        client
+            .subscribe(
+                topics.discovery_configs(component, dongle_id),
+                QoS::AtMostOnce,
+            )
+            .await?;
+    }
+
+    Ok(client)
+}
+
+/// Whether a discovery config found on the broker is for something we no longer announce.
+pub fn is_stale_config(
+    component: &str,
+    object_id: &str,
+    slots: &BTreeMap<u8, String>,
+    config: &Config,
+) -> bool {
+    let is_blind = slots.values().any(|addr| addr == object_id);
+
+    match component {
+        HA_MQTT_COMPONENT => {
+            let is_group = object_id
+                .strip_prefix("group_")
+                .is_some_and(|name| config.groups.contains_key(name));
+
+            !is_blind && !is_group
+        }
+
+        "button" => !is_blind && !DONGLE_BUTTONS.contains(&object_id),
+
+        "scene" => !object_id
+            .strip_prefix("scene_")
+            .is_some_and(|name| config.scenes.contains_key(name)),
+
+        "switch" => !object_id
+            .strip_prefix("schedule_")
+            .is_some_and(|name| config.schedules.contains_key(name)),
+
+        "sensor" => {
+            let is_schedule = object_id
+                .strip_prefix("schedule_")
+                .and_then(|name| name.strip_suffix("_next_run"))
+                .is_some_and(|name| config.schedules.contains_key(name));
+
+            !is_schedule && !DONGLE_SENSORS.contains(&object_id)
+        }
+
+        _ => false,
+    }
+}
+
+/// Deletes an entity from Home Assistant, through an empty retained config.
+pub async fn delete_config(client: &AsyncClient, config_topic: &str) -> Result<()> {
+    Ok(client
+        .publish(config_topic, QoS::AtLeastOnce, true, "")
+        .await?)
+}
+
+/// Announces each group of blinds as a cover of its own, attached to the dongle.
+pub async fn setup_group_autodiscovery<'a>(
+    client: &'a AsyncClient,
+    dongle: &DongleInfo,
+    config: &Config,
+) -> Result<&'a AsyncClient> {
+    let topics = config.topics();
+    let serial = dongle.serial();
+
+    for name in config.groups.keys() {
+        let command_topic = topics.group(name, "set");
+        let config_topic =
+            topics.discovery_config(HA_MQTT_COMPONENT, serial, &format!("group_{}", name));
+
+        let group_config = json!({
+            "availability": [
+                {
+                    "topic": topics.dongle("state"),
+                }
+            ],
+            "device": dongle_device(dongle),
+            "unique_id": format!("{}_group_{}", serial, name),
+            "name": group_name(name),
+            "retain": true,
+            "payload_close": "DOWN",
+            "payload_open": "UP",
+            "payload_stop": "STOP",
+            "command_topic": command_topic
+        });
+        let config_payload = serde_json::to_string(&group_config).unwrap();
+
+        client.subscribe(command_topic, QoS::AtMostOnce).await?;
+
+        client
+            .publish(config_topic, QoS::AtLeastOnce, true, config_payload)
+            .await?;
+    }
+
+    Ok(client)
+}
+
+/// Announces each scene, attached to the dongle.
+pub async fn setup_scene_autodiscovery<'a>(
+    client: &'a AsyncClient,
+    dongle: &DongleInfo,
+    config: &Config,
+) -> Result<&'a AsyncClient> {
+    let topics = config.topics();
+    let serial = dongle.serial();
+
+    for name in config.scenes.keys() {
+        let command_topic = topics.scene(name, "set");
+        let object_id = format!("scene_{}", name);
+        let config_topic = topics.discovery_config("scene", serial, &object_id);
+
+        let scene_config = json!({
+            "availability": [
+                {
+                    "topic": topics.dongle("state"),
+                }
+            ],
+            "device": dongle_device(dongle),
+            "unique_id": format!("{}_{}", serial, object_id),
+            "name": group_name(name),
+            "payload_on": "ON",
+            "command_topic": command_topic
+        });
+        let config_payload = serde_json::to_string(&scene_config).unwrap();
+
+        client.subscribe(command_topic, QoS::AtMostOnce).await?;
+
+        client
+            .publish(config_topic, QoS::AtLeastOnce, true, config_payload)
+            .await?;
+    }
+
+    Ok(client)
+}
+
+/// Announces each schedule as a switch turning it on and off, along with a sensor telling
+/// when it next runs.
+pub async fn setup_schedule_autodiscovery<'a>(
+    client: &'a AsyncClient,
+    dongle: &DongleInfo,
+    config: &Config,
+) -> Result<&'a AsyncClient> {
+    let topics = config.topics();
+    let serial = dongle.serial();
+
+    for name in config.schedules.keys() {
+        let command_topic = topics.schedule(name, "set");
+
+        let entities = [
+            (
+                "switch",
+                format!("schedule_{}", name),
+                json!({
+                    "name": format!("Schedule {}", group_name(name).to_lowercase()),
+                    "icon": "mdi:calendar-clock",
+                    "entity_category": "config",
+                    "command_topic": command_topic,
+                    "state_topic": topics.schedule(name, "state"),
+                    "payload_on": "ON",
+                    "payload_off": "OFF",
+                }),
+            ),
+            (
+                "sensor",
+                format!("schedule_{}_next_run", name),
+                json!({
+                    "name": format!("Next run of {}", group_name(name).to_lowercase()),
+                    "device_class": "timestamp",
+                    "state_topic": topics.schedule(name, "next_run"),
+                }),
+            ),
+        ];
+
+        for (component, object_id, mut entity) in entities {
+            entity["unique_id"] = json!(format!("{}_{}", serial, object_id));
+            entity["device"] = dongle_device(dongle);
+            entity["availability"] = json!([
+                {
+                    "topic": topics.dongle("state"),
+                }
+            ]);
+
+            let config_topic = topics.discovery_config(component, serial, &object_id);
+            let config_payload = serde_json::to_string(&entity).unwrap();
+
+            client
+                .publish(config_topic, QoS::AtLeastOnce, true, config_payload)
+                .await?;
+        }
+
+        client.subscribe(command_topic, QoS::AtMostOnce).await?;
+    }
+
+    Ok(client)
+}
+
+/// Announces the dongle itself, along with its diagnostic sensors and reboot button.
+pub async fn setup_dongle_autodiscovery<'a>(
+    client: &'a AsyncClient,
+    dongle: &DongleInfo,
+    config: &Config,
+) -> Result<&'a AsyncClient> {
+    let topics = config.topics();
+    let serial = dongle.serial();
+
+    let entities = [
+        (
+            "sensor",
+            "rssi",
+            json!({
+                "name": "Signal strength",
+                "device_class": "signal_strength",
+                "unit_of_measurement": "dBm",
+                "state_class": "measurement",
+                "state_topic": topics.dongle("rssi"),
+            }),
+        ),
+        (
+            "sensor",
+            "last_result",
+            json!({
+                "name": "Last command result",
+                "icon": "mdi:message-reply-text",
+                "state_topic": topics.dongle("last_result"),
+            }),
+        ),
+        (
+            "sensor",
+            "errors",
+            json!({
+                "name": "Command errors",
+                "icon": "mdi:alert-circle",
+                "state_class": "total_increasing",
+                "state_topic": topics.dongle("errors"),
+            }),
+        ),
+        (
+            "sensor",
+            "queue",
+            json!({
+                "name": "Queued commands",
+                "icon": "mdi:tray-full",
+                "state_class": "measurement",
+                "state_topic": topics.dongle("queue"),
+            }),
+        ),
+        (
+            "button",
+            "pair",
+            json!({
+                "name": "Pair new blind",
+                "icon": "mdi:link-plus",
+                "entity_category": "config",
+                "command_topic": topics.dongle("pair"),
+            }),
+        ),
+        (
+            "button",
+            "reboot",
+            json!({
+                "name": "Reboot",
+                "device_class": "restart",
+                "command_topic": topics.dongle("reboot"),
+            }),
+        ),
+    ];
+
+    for (component, object_id, mut entity) in entities {
+        entity["unique_id"] = json!(format!("{}_{}", serial, object_id));
+        if entity.get("entity_category").is_none() {
+            entity["entity_category"] = json!("diagnostic");
+        }
+        entity["device"] = dongle_device(dongle);
+        entity["availability"] = json!([
+            {
+                "topic": topics.dongle("state"),
+            }
+        ]);
+
+        let config_topic = topics.discovery_config(component, serial, object_id);
+        let config_payload = serde_json::to_string(&entity).unwrap();
 
         client
             .publish(config_topic, QoS::AtLeastOnce, true, config_payload)
             .await?;
     }
 
+    for leaf in DONGLE_COMMANDS {
+        client
+            .subscribe(topics.dongle(leaf), QoS::AtMostOnce)
+            .await?;
+    }
+
     Ok(client)
 }
 
-pub async fn set_state_on(client: &AsyncClient) -> Result<()> {
-    let state_topic = format!("{}/dongle/state", HA_MQTT_NODEID);
+pub async fn set_state_on(client: &AsyncClient, topics: &Topics) -> Result<()> {
+    let state_topic = topics.dongle("state");
 
     Ok(client
         .publish(state_topic, QoS::AtLeastOnce, true, "online")
         .await?)
 }
 
-fn config_for_blind(id: u8, dongle_serial: &str, addr: &str) -> serde_json::Value {
+pub async fn set_state_off(client: &AsyncClient, topics: &Topics) -> Result<()> {
+    let state_topic = topics.dongle("state");
+
+    Ok(client
+        .publish(state_topic, QoS::AtLeastOnce, true, "offline")
+        .await?)
+}
+
+/// Marks the dongle as offline should the bridge lose its connection to the broker.
+pub fn last_will(topics: &Topics) -> LastWill {
+    let state_topic = topics.dongle("state");
+
+    LastWill::new(state_topic, "offline", QoS::AtLeastOnce, true)
+}
+
+pub async fn publish_rssi(client: &AsyncClient, topics: &Topics, rssi: i32) -> Result<()> {
+    let rssi_topic = topics.dongle("rssi");
+
+    Ok(client
+        .publish(rssi_topic, QoS::AtMostOnce, false, rssi.to_string())
+        .await?)
+}
+
+pub async fn publish_queue_depth(
+    client: &AsyncClient,
+    topics: &Topics,
+    depth: usize,
+) -> Result<()> {
+    let queue_topic = topics.dongle("queue");
+
+    Ok(client
+        .publish(queue_topic, QoS::AtMostOnce, false, depth.to_string())
+        .await?)
+}
+
+pub async fn publish_position(
+    client: &AsyncClient,
+    topics: &Topics,
+    id: u8,
+    position: f64,
+) -> Result<()> {
+    let position_topic = topics.cover(id, "position");
+
+    Ok(client
+        .publish(
+            position_topic,
+            QoS::AtLeastOnce,
+            true,
+            (position.round() as u8).to_string(),
+        )
+        .await?)
+}
+
+pub async fn publish_tilt(client: &AsyncClient, topics: &Topics, id: u8, angle: f64) -> Result<()> {
+    let tilt_topic = topics.cover(id, "tilt_state");
+
+    Ok(client
+        .publish(
+            tilt_topic,
+            QoS::AtLeastOnce,
+            true,
+            (angle.round() as u8).to_string(),
+        )
+        .await?)
+}
+
+/// Tells which members of a group acknowledged the last command sent to it.
+pub async fn publish_group_result(
+    client: &AsyncClient,
+    topics: &Topics,
+    name: &str,
+    succeeded: &[u8],
+    superseded: &[u8],
+    failed: &[u8],
+    correlation_id: Option<&Value>,
+) -> Result<()> {
+    let result_topic = topics.group(name, "result");
+
+    let mut result = json!({
+        "result": if failed.is_empty() { "OK" } else { "PARTIAL_FAILURE" },
+        "succeeded": succeeded,
+        "superseded": superseded,
+        "failed": failed,
+    });
+
+    if let Some(correlation_id) = correlation_id {
+        result["correlation_id"] = correlation_id.clone();
+    }
+
+    Ok(client
+        .publish(result_topic, QoS::AtLeastOnce, false, result.to_string())
+        .await?)
+}
+
+/// Tells how a command sent to a blind went: the dongle answers each frame it sent with
+/// the blind's new rolling code.
+pub async fn publish_cover_result(
+    client: &AsyncClient,
+    topics: &Topics,
+    id: u8,
+    order: Order,
+    outcome: &Outcome,
+    correlation_id: Option<&Value>,
+) -> Result<()> {
+    let result_topic = topics.cover(id, "result");
+
+    let mut result = json!({
+        "command": order.as_str(),
+        "timestamp": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+    });
+
+    match outcome {
+        Ok(reply) => {
+            result["result"] = json!("OK");
+
+            if let Reply::Blind(blind) = reply {
+                result["rolling_code"] = blind.address_val[2].clone();
+            }
+        }
+
+        Err(e) => {
+            result["result"] = json!("ERROR");
+            result["message"] = json!(e.to_string());
+        }
+    }
+
+    if let Some(correlation_id) = correlation_id {
+        result["correlation_id"] = correlation_id.clone();
+    }
+
+    Ok(client
+        .publish(result_topic, QoS::AtLeastOnce, false, result.to_string())
+        .await?)
+}
+
+/// Tells whether a schedule is on, and when it next runs (`None` if it does not).
+pub async fn publish_schedule(client: &AsyncClient, topics: &Topics, rule: &Rule) -> Result<()> {
+    let state = if rule.enabled { "ON" } else { "OFF" };
+
+    let next_run = match rule.next() {
+        Some(next) => next.to_rfc3339_opts(SecondsFormat::Secs, false),
+        None => "None".to_string(),
+    };
+
+    client
+        .publish(
+            topics.schedule(&rule.name, "state"),
+            QoS::AtLeastOnce,
+            true,
+            state,
+        )
+        .await?;
+
+    Ok(client
+        .publish(
+            topics.schedule(&rule.name, "next_run"),
+            QoS::AtLeastOnce,
+            true,
+            next_run,
+        )
+        .await?)
+}
+
+/// Tells whether a scene ran through, or at which step it was aborted.
+pub async fn publish_scene_result(
+    client: &AsyncClient,
+    topics: &Topics,
+    name: &str,
+    outcome: &Result<(), Failure>,
+) -> Result<()> {
+    let result_topic = topics.scene(name, "result");
+
+    let result = json!({
+        "result": if outcome.is_ok() { "OK" } else { "ERROR" },
+        "message": outcome.as_ref().err().map(ToString::to_string),
+        "timestamp": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+    });
+
+    Ok(client
+        .publish(result_topic, QoS::AtLeastOnce, false, result.to_string())
+        .await?)
+}
+
+pub async fn publish_command_result(
+    client: &AsyncClient,
+    topics: &Topics,
+    result: &str,
+    errors: u64,
+) -> Result<()> {
+    let result_topic = topics.dongle("last_result");
+    let errors_topic = topics.dongle("errors");
+
+    client
+        .publish(result_topic, QoS::AtLeastOnce, true, result)
+        .await?;
+
+    Ok(client
+        .publish(errors_topic, QoS::AtLeastOnce, true, errors.to_string())
+        .await?)
+}
+
+fn dongle_device(dongle: &DongleInfo) -> serde_json::Value {
     json!({
+        "manufacturer": "Enki",
+        "model": "Somfy RTS extension dongle",
+        "name": "Somfy RTS dongle",
+        "sw_version": dongle.firmware,
+        "identifiers": [
+            dongle.serial()
+        ]
+    })
+}
+
+fn config_for_blind(id: u8, dongle_serial: &str, addr: &str, config: &Config) -> serde_json::Value {
+    let topics = config.topics();
+    let blind = config.blind(id);
+
+    let device_class = blind.map(|blind| blind.device_class).unwrap_or_default();
+
+    let mut cover = json!({
         "availability": [   
             {
-                "topic": format!("{}/dongle/state", HA_MQTT_NODEID),
+                "topic": topics.dongle("state"),
             }
         ],
-        "device_class" : "shutter",
-        "device": {
-            "manufacturer": "Somfy",
-            "model": "Shutter",
-            "name": "Somfy RTS Shutter",
-            "identifiers": [
-                format!("{}_{}", dongle_serial, addr)
-            ]
-        },
-        "name": format!("Somfy Shutter n°{} ({})", id, addr),
+        "device_class" : device_class.as_str(),
+        "device": blind_device(id, dongle_serial, addr, config),
+        "unique_id": format!("{}_{}", dongle_serial, addr),
+        "name": null,
         "retain":true,
         "payload_close": "DOWN",
         "payload_open": "UP",
         "payload_stop": "STOP",
-        "command_topic": format!("{}/cover/{}/set", HA_MQTT_NODEID, id)
+        "command_topic": topics.cover(id, "set")
+    });
+
+    if blind.and_then(|blind| blind.travel_time.as_ref()).is_some() {
+        cover["position_topic"] = json!(topics.cover(id, "position"));
+    }
+
+    if blind.and_then(|blind| blind.tilt_time.as_ref()).is_some() {
+        cover["tilt_command_topic"] = json!(topics.cover(id, "tilt"));
+        cover["tilt_status_topic"] = json!(topics.cover(id, "tilt_state"));
+        cover["tilt_min"] = json!(0);
+        cover["tilt_max"] = json!(100);
+        cover["tilt_opened_value"] = json!(100);
+        cover["tilt_closed_value"] = json!(0);
+    }
+
+    cover
+}
+
+fn my_button_for_blind(
+    id: u8,
+    dongle_serial: &str,
+    addr: &str,
+    config: &Config,
+) -> serde_json::Value {
+    let topics = config.topics();
+
+    json!({
+        "availability": [
+            {
+                "topic": topics.dongle("state"),
+            }
+        ],
+        "device": blind_device(id, dongle_serial, addr, config),
+        "unique_id": format!("{}_{}_my", dongle_serial, addr),
+        "name": "My position",
+        "icon": "mdi:star",
+        "payload_press": "MY",
+        "command_topic": topics.cover(id, "set")
     })
 }
+
+fn blind_device(id: u8, dongle_serial: &str, addr: &str, config: &Config) -> serde_json::Value {
+    let blind = config.blind(id);
+
+    let device_class = blind.map(|blind| blind.device_class).unwrap_or_default();
+
+    let name = blind
+        .and_then(|blind| blind.name.clone())
+        .unwrap_or_else(|| format!("Somfy {} n°{} ({})", device_class.model(), id, addr));
+
+    let mut device = json!({
+        "manufacturer": "Somfy",
+        "model": format!("RTS {}", device_class.model()),
+        "name": name,
+        "identifiers": [
+            format!("{}_{}", dongle_serial, addr)
+        ],
+        "via_device": dongle_serial
+    });
+
+    if let Some(area) = blind.and_then(|blind| blind.area.as_deref()) {
+        device["suggested_area"] = json!(area);
+    }
+
+    device
+}
+
+/// Turns a group key such as `ground_floor` into "Ground floor".
+fn group_name(name: &str) -> String {
+    let name = name.replace(['_', '-'], " ");
+
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
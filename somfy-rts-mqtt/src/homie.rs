@@ -0,0 +1,253 @@
+use anyhow::Result;
+use rumqttc::{AsyncClient, EventLoop, LastWill, QoS};
+
+use crate::config::Config;
+
+/// Every attribute of up to 100 nodes is queued before the event loop is first polled.
+const HOMIE_REQUEST_CAPACITY: usize = 2048;
+
+const HOMIE_VERSION: &str = "4.0.0";
+
+/// Values of the `command` property of each node.
+const ORDERS: &str = "UP,DOWN,STOP,MY";
+
+/// Lifecycle of the device, as told by its `$state` attribute.
+#[derive(Debug, Clone, Copy)]
+pub enum State {
+    Init,
+    Ready,
+    Lost,
+}
+
+/// The dongle, published as a device following the Homie convention, with a node per slot.
+///
+/// It has its own connection to the broker, since `$state` needs a last will of its own.
+pub struct Homie {
+    client: AsyncClient,
+    /// Base topic of the device, `<base_topic>/<device id>`.
+    device: String,
+    name: String,
+}
+
+impl State {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Init => "init",
+            Self::Ready => "ready",
+            Self::Lost => "lost",
+        }
+    }
+}
+
+/// Homie ids only hold lowercase letters, digits and hyphens.
+fn device_id(serial: &str) -> String {
+    let serial = serial
+        .chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            c @ ('a'..='z' | '0'..='9') => c,
+            _ => '-',
+        })
+        .collect::<String>();
+
+    format!("somfy-rts-{}", serial)
+}
+
+fn node_id(id: u8) -> String {
+    format!("blind-{}", id)
+}
+
+impl Homie {
+    /// Opens the device's own connection to the broker, as `<client_id>-homie`.
+    pub fn new(config: &Config, serial: &str) -> Result<(Self, EventLoop)> {
+        let device = format!("{}/{}", config.homie.base_topic, device_id(serial));
+
+        let mut config = config.clone();
+        config.mqtt.client_id = format!("{}-homie", config.mqtt.client_id);
+
+        let mut options = config.mqtt_options()?;
+
+        options.set_last_will(LastWill::new(
+            format!("{}/$state", device),
+            State::Lost.as_str(),
+            QoS::AtLeastOnce,
+            true,
+        ));
+
+        let (client, eventloop) = AsyncClient::new(options, HOMIE_REQUEST_CAPACITY);
+
+        let homie = Homie {
+            client,
+            device,
+            name: format!("Somfy RTS dongle {}", serial),
+        };
+
+        Ok((homie, eventloop))
+    }
+
+    fn topic(&self, leaf: &str) -> String {
+        format!("{}/{}", self.device, leaf)
+    }
+
+    async fn publish(&self, leaf: &str, value: impl Into<Vec<u8>>) -> Result<()> {
+        Ok(self
+            .client
+            .publish(self.topic(leaf), QoS::AtLeastOnce, true, value)
+            .await?)
+    }
+
+    pub async fn set_state(&self, state: State) -> Result<()> {
+        self.publish("$state", state.as_str()).await
+    }
+
+    /// Publishes the device along with a node per slot in `blinds`, then marks it ready.
+    pub async fn announce(&self, blinds: &[u8], config: &Config) -> Result<()> {
+        self.set_state(State::Init).await?;
+
+        self.publish("$homie", HOMIE_VERSION).await?;
+        self.publish("$name", self.name.as_str()).await?;
+        self.publish("$implementation", "somfy-rts-mqtt").await?;
+        self.publish("$extensions", "").await?;
+
+        for id in blinds {
+            self.announce_node(*id, config).await?;
+        }
+
+        self.publish_nodes(blinds).await?;
+
+        self.set_state(State::Ready).await
+    }
+
+    /// Adds the node of a freshly paired blind to the device.
+    pub async fn add_node(&self, id: u8, blinds: &[u8], config: &Config) -> Result<()> {
+        self.set_state(State::Init).await?;
+
+        self.announce_node(id, config).await?;
+        self.publish_nodes(blinds).await?;
+
+        self.set_state(State::Ready).await
+    }
+
+    /// Removes the node of a slot that was reset, clearing its retained attributes.
+    pub async fn remove_node(&self, id: u8, blinds: &[u8], config: &Config) -> Result<()> {
+        self.set_state(State::Init).await?;
+
+        self.client
+            .unsubscribe(self.topic(&format!("{}/command/set", node_id(id))))
+            .await?;
+
+        for (leaf, _) in node_attributes(id, config) {
+            self.publish(&leaf, "").await?;
+        }
+
+        self.publish_nodes(blinds).await?;
+
+        self.set_state(State::Ready).await
+    }
+
+    /// Subscribes again to the `command` property of the node of each slot in `blinds`, once
+    /// the device connected to the broker with a new session, then marks it ready again: its
+    /// last will may have marked it lost in the meantime.
+    pub async fn resubscribe(&self, blinds: &[u8]) -> Result<()> {
+        for id in blinds {
+            self.subscribe_node(*id).await?;
+        }
+
+        self.set_state(State::Ready).await
+    }
+
+    async fn announce_node(&self, id: u8, config: &Config) -> Result<()> {
+        for (leaf, value) in node_attributes(id, config) {
+            self.publish(&leaf, value).await?;
+        }
+
+        self.subscribe_node(id).await
+    }
+
+    async fn subscribe_node(&self, id: u8) -> Result<()> {
+        self.client
+            .subscribe(
+                self.topic(&format!("{}/command/set", node_id(id))),
+                QoS::AtMostOnce,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn publish_nodes(&self, blinds: &[u8]) -> Result<()> {
+        let nodes = blinds
+            .iter()
+            .map(|id| node_id(*id))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        self.publish("$nodes", nodes).await
+    }
+
+    pub async fn publish_position(&self, id: u8, position: f64) -> Result<()> {
+        let leaf = format!("{}/position", node_id(id));
+
+        self.publish(&leaf, (position.round() as u8).to_string())
+            .await
+    }
+
+    /// The slot whose `command` property `topic` sets, if it is one.
+    pub fn route<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        let rest = topic.strip_prefix(&self.device)?.strip_prefix('/')?;
+
+        match rest.split('/').collect::<Vec<_>>().as_slice() {
+            [node, "command", "set"] => node.strip_prefix("blind-"),
+            _ => None,
+        }
+    }
+}
+
+/// Attributes of the node for slot `id`, and of its properties, along with their values.
+///
+/// Only blinds with a travel time have a `position`, estimated by the bridge.
+fn node_attributes(id: u8, config: &Config) -> Vec<(String, String)> {
+    let blind = config.blind(id);
+
+    let name = blind
+        .and_then(|blind| blind.name.clone())
+        .unwrap_or_else(|| format!("Blind {}", id));
+
+    let device_class = blind.map(|blind| blind.device_class).unwrap_or_default();
+
+    let has_position = blind.and_then(|blind| blind.travel_time.as_ref()).is_some();
+
+    let node = node_id(id);
+
+    let mut attributes = vec![
+        ("$name", name),
+        ("$type", device_class.as_str().to_string()),
+        (
+            "$properties",
+            if has_position {
+                "command,position"
+            } else {
+                "command"
+            }
+            .to_string(),
+        ),
+        ("command/$name", "Command".to_string()),
+        ("command/$datatype", "enum".to_string()),
+        ("command/$format", ORDERS.to_string()),
+        ("command/$settable", "true".to_string()),
+        ("command/$retained", "false".to_string()),
+    ];
+
+    if has_position {
+        attributes.extend([
+            ("position/$name", "Position".to_string()),
+            ("position/$datatype", "integer".to_string()),
+            ("position/$format", "0:100".to_string()),
+            ("position/$unit", "%".to_string()),
+        ]);
+    }
+
+    attributes
+        .into_iter()
+        .map(|(leaf, value)| (format!("{}/{}", node, leaf), value))
+        .collect()
+}
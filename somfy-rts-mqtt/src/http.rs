@@ -0,0 +1,713 @@
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use somfy_rts::{scene, AddressVal, LedAction, LedColor, RtsCommand};
+use tokio::{
+    net::TcpListener,
+    select,
+    sync::{broadcast::error::RecvError, mpsc},
+};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    command::Order,
+    events::Events,
+    metrics,
+    queue::{CommandQueue, Failure, Reply, Request},
+};
+
+/// A dongle, as driven through the HTTP API.
+#[derive(Clone)]
+pub struct Dongle {
+    pub serial: String,
+
+    pub firmware: String,
+
+    pub queue: CommandQueue,
+
+    /// Scenes of the dongle, resolved to what running them sends.
+    pub scenes: Arc<BTreeMap<String, Vec<scene::Action>>>,
+}
+
+/// What the event stream needs: every dongle, and their events.
+#[derive(Clone)]
+struct Hub {
+    dongles: Arc<Vec<Dongle>>,
+    events: Events,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "somfy-rts-mqtt",
+        description = "Drives RTS objects through an Enki \"Somfy RTS\" dongle. When several \
+                       dongles are driven, each one's API lives under `/dongles/{serial}`. \
+                       Events of every dongle are streamed over a WebSocket at `/events`, \
+                       and Prometheus metrics served at `/metrics`."
+    ),
+    paths(
+        get_dongle,
+        set_led,
+        list_blinds,
+        operate_blind,
+        remove_blind,
+        run_scene
+    ),
+    components(schemas(Action))
+)]
+struct ApiDoc;
+
+#[derive(Serialize, ToSchema)]
+struct DongleStatus {
+    serial: String,
+
+    firmware: String,
+
+    /// Signal strength, as measured by the dongle.
+    rssi: i32,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Blind {
+    /// Slot of the blind on the dongle.
+    id: u8,
+
+    /// RTS address of the blind (hexadecimal).
+    address: Option<String>,
+
+    /// Rolling code of the next frame sent to the blind (hexadecimal).
+    rolling_code: Option<String>,
+}
+
+/// How a request went through the dongle.
+#[derive(Serialize, ToSchema)]
+struct CommandResult {
+    /// `OK` or `ERROR`.
+    result: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rolling_code: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+/// Orders that can be sent to a blind.
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Up,
+    Down,
+    Stop,
+    My,
+    Prog,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum Color {
+    Red,
+    Green,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum Lighting {
+    #[default]
+    Fix,
+    Blink,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct Led {
+    color: Color,
+
+    #[serde(default)]
+    action: Lighting,
+
+    /// How long the LED stays lit, in the dongle's own unit.
+    length: u16,
+}
+
+/// A command received over the event stream.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StreamCommand {
+    /// Serial of the dongle, which can be left out when there is only one.
+    dongle: Option<String>,
+
+    blind: u8,
+
+    command: Order,
+
+    #[serde(default)]
+    correlation_id: Option<Value>,
+}
+
+struct ApiError(StatusCode, String);
+
+impl From<Failure> for ApiError {
+    fn from(failure: Failure) -> Self {
+        let status = match failure {
+            Failure::Refused(_) => StatusCode::BAD_GATEWAY,
+            Failure::Error(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Failure::Superseded => StatusCode::CONFLICT,
+        };
+
+        ApiError(status, failure.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let ApiError(status, message) = self;
+
+        let result = CommandResult {
+            result: "ERROR".to_string(),
+            rolling_code: None,
+            message: Some(message),
+        };
+
+        (status, Json(result)).into_response()
+    }
+}
+
+impl Action {
+    fn request(&self, id: u8) -> Request {
+        match self {
+            Self::Up => Request::Operate(RtsCommand::Up(id)),
+            Self::Down => Request::Operate(RtsCommand::Down(id)),
+            Self::Stop => Request::Operate(RtsCommand::Stop(id)),
+            Self::My => Request::Operate(RtsCommand::My(id)),
+
+            // Pairing through the bridge announces the blind right away.
+            Self::Prog => Request::Pair(id),
+        }
+    }
+}
+
+impl From<(u8, &AddressVal)> for Blind {
+    /// The dongle does not say which slot it read: the id is the one it was asked for.
+    fn from((id, blind): (u8, &AddressVal)) -> Self {
+        let [_, address, rolling_code] = &blind.address_val;
+
+        Blind {
+            id,
+            address: address.as_str().map(str::to_string),
+            rolling_code: rolling_code.as_str().map(str::to_string),
+        }
+    }
+}
+
+/// Queues a request for the dongle, and waits for it to go through.
+async fn execute(queue: &CommandQueue, request: Request) -> Result<Reply, ApiError> {
+    match queue.push(request).await {
+        Ok(outcome) => outcome.map_err(ApiError::from),
+
+        Err(_) => Err(ApiError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Lost the dongle".to_string(),
+        )),
+    }
+}
+
+fn check_slot(id: u8) -> Result<(), ApiError> {
+    match id {
+        1..=100 => Ok(()),
+        _ => Err(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("No slot {}, expected 1 to 100", id),
+        )),
+    }
+}
+
+fn ok(rolling_code: Option<String>) -> Json<CommandResult> {
+    Json(CommandResult {
+        result: "OK".to_string(),
+        rolling_code,
+        message: None,
+    })
+}
+
+/// Tells about the dongle, and how well it hears.
+#[utoipa::path(
+    get,
+    path = "/dongle",
+    responses(
+        (status = 200, body = DongleStatus),
+        (status = 502, description = "Refused by the dongle", body = CommandResult),
+        (status = 503, description = "Dongle unreachable", body = CommandResult),
+    )
+)]
+async fn get_dongle(State(dongle): State<Dongle>) -> Result<Json<DongleStatus>, ApiError> {
+    let rssi = match execute(&dongle.queue, Request::Alive).await? {
+        Reply::Alive(alive) => alive.rssi_val,
+        _ => i32::default(),
+    };
+
+    Ok(Json(DongleStatus {
+        serial: dongle.serial,
+        firmware: dongle.firmware,
+        rssi,
+    }))
+}
+
+/// Lights the dongle's LED up.
+#[utoipa::path(
+    post,
+    path = "/dongle/led",
+    request_body = Led,
+    responses(
+        (status = 200, body = CommandResult),
+        (status = 502, description = "Refused by the dongle", body = CommandResult),
+        (status = 503, description = "Dongle unreachable", body = CommandResult),
+    )
+)]
+async fn set_led(
+    State(dongle): State<Dongle>,
+    Json(led): Json<Led>,
+) -> Result<Json<CommandResult>, ApiError> {
+    let color = match led.color {
+        Color::Red => LedColor::Red,
+        Color::Green => LedColor::Green,
+    };
+
+    let action = match led.action {
+        Lighting::Fix => LedAction::Fix,
+        Lighting::Blink => LedAction::Blink,
+    };
+
+    execute(&dongle.queue, Request::Led(color, action, led.length)).await?;
+
+    Ok(ok(None))
+}
+
+/// Lists the slots holding a blind, querying each of them on the dongle.
+#[utoipa::path(
+    get,
+    path = "/blinds",
+    responses(
+        (status = 200, body = [Blind]),
+        (status = 503, description = "Dongle unreachable", body = CommandResult),
+    )
+)]
+async fn list_blinds(State(dongle): State<Dongle>) -> Result<Json<Vec<Blind>>, ApiError> {
+    let blinds = match execute(&dongle.queue, Request::ListBlinds).await? {
+        Reply::Blinds(blinds) => blinds
+            .iter()
+            .map(|(id, blind)| (*id, blind).into())
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    Ok(Json(blinds))
+}
+
+/// Sends an order to the blind in slot `id`.
+#[utoipa::path(
+    post,
+    path = "/blinds/{id}/{action}",
+    params(
+        ("id" = u8, Path, description = "Slot of the blind, 1 to 100"),
+        ("action" = Action, Path, description = "Order to send"),
+    ),
+    responses(
+        (status = 200, body = CommandResult),
+        (status = 404, description = "No such slot", body = CommandResult),
+        (status = 409, description = "Superseded by a newer command", body = CommandResult),
+        (status = 502, description = "Refused by the dongle", body = CommandResult),
+        (status = 503, description = "Dongle unreachable", body = CommandResult),
+    )
+)]
+async fn operate_blind(
+    State(dongle): State<Dongle>,
+    Path((id, action)): Path<(u8, Action)>,
+) -> Result<Json<CommandResult>, ApiError> {
+    check_slot(id)?;
+
+    let rolling_code = match execute(&dongle.queue, action.request(id)).await? {
+        Reply::Blind(blind) => Blind::from((id, &blind)).rolling_code,
+        _ => None,
+    };
+
+    Ok(ok(rolling_code))
+}
+
+/// Runs scene `name`, answering once every step went through, or one of them failed.
+#[utoipa::path(
+    post,
+    path = "/scenes/{name}",
+    params(("name" = String, Path, description = "Name of the scene")),
+    responses(
+        (status = 200, body = CommandResult),
+        (status = 404, description = "No such scene", body = CommandResult),
+        (status = 409, description = "A step was superseded by a newer command", body = CommandResult),
+        (status = 502, description = "A step was refused by the dongle", body = CommandResult),
+        (status = 503, description = "Dongle unreachable", body = CommandResult),
+    )
+)]
+async fn run_scene(
+    State(dongle): State<Dongle>,
+    Path(name): Path<String>,
+) -> Result<Json<CommandResult>, ApiError> {
+    let actions = dongle
+        .scenes
+        .get(&name)
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("No scene {}", name)))?;
+
+    dongle.queue.run_scene(actions).await?;
+
+    Ok(ok(None))
+}
+
+/// Resets slot `id`, so that the dongle no longer commands the blind it held.
+#[utoipa::path(
+    delete,
+    path = "/blinds/{id}",
+    params(("id" = u8, Path, description = "Slot of the blind, 1 to 100")),
+    responses(
+        (status = 200, body = CommandResult),
+        (status = 404, description = "No such slot", body = CommandResult),
+        (status = 502, description = "Refused by the dongle", body = CommandResult),
+        (status = 503, description = "Dongle unreachable", body = CommandResult),
+    )
+)]
+async fn remove_blind(
+    State(dongle): State<Dongle>,
+    Path(id): Path<u8>,
+) -> Result<Json<CommandResult>, ApiError> {
+    check_slot(id)?;
+
+    execute(&dongle.queue, Request::Unpair(id)).await?;
+
+    Ok(ok(None))
+}
+
+async fn events(State(hub): State<Hub>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| stream_events(socket, hub))
+}
+
+/// Sends every event down the socket, and runs the commands coming up from it, answering
+/// each one with a `result` message once it went through the dongle.
+async fn stream_events(mut socket: WebSocket, hub: Hub) {
+    let mut events = hub.events.subscribe();
+
+    let (results, mut pending) = mpsc::unbounded_channel();
+
+    loop {
+        let message = select! {
+            event = events.recv() => match event {
+                Ok(event) => json!(event),
+
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(target: "http", "Event stream is lagging, dropped {} events.", missed);
+                    continue;
+                }
+
+                Err(RecvError::Closed) => return,
+            },
+
+            Some(result) = pending.recv() => result,
+
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let hub = hub.clone();
+                    let results = results.clone();
+
+                    tokio::spawn(async move {
+                        let _ = results.send(stream_command(&hub, text.as_str()).await);
+                    });
+
+                    continue;
+                }
+
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        if socket
+            .send(Message::Text(message.to_string().into()))
+            .await
+            .is_err()
+        {
+            debug!(target: "http", "Event stream closed.");
+            return;
+        }
+    }
+}
+
+/// Runs a command received over the event stream.
+async fn stream_command(hub: &Hub, text: &str) -> Value {
+    let command = match serde_json::from_str::<StreamCommand>(text) {
+        Ok(command) => command,
+
+        Err(e) => {
+            return json!({
+                "type": "result",
+                "result": "ERROR",
+                "message": format!("bad command: {}", e),
+            })
+        }
+    };
+
+    let outcome = match run_stream_command(hub, &command).await {
+        Ok(rolling_code) => ok(rolling_code).0,
+
+        Err(ApiError(_, message)) => CommandResult {
+            result: "ERROR".to_string(),
+            rolling_code: None,
+            message: Some(message),
+        },
+    };
+
+    json!({
+        "type": "result",
+        "dongle": command.dongle,
+        "blind": command.blind,
+        "command": command.command.as_str(),
+        "result": outcome.result,
+        "rolling_code": outcome.rolling_code,
+        "message": outcome.message,
+        "correlation_id": command.correlation_id,
+    })
+}
+
+async fn run_stream_command(
+    hub: &Hub,
+    command: &StreamCommand,
+) -> Result<Option<String>, ApiError> {
+    check_slot(command.blind)?;
+
+    let dongle = match (command.dongle.as_deref(), hub.dongles.as_slice()) {
+        (None, [dongle]) => dongle,
+
+        (None, _) => {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "Several dongles are driven, tell which one".to_string(),
+            ))
+        }
+
+        (Some(serial), dongles) => dongles
+            .iter()
+            .find(|dongle| dongle.serial == serial)
+            .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("No dongle {}", serial)))?,
+    };
+
+    let request = command.command.request(command.blind, 1);
+
+    match execute(&dongle.queue, request).await? {
+        Reply::Blind(blind) => Ok(Blind::from((command.blind, &blind)).rolling_code),
+        _ => Ok(None),
+    }
+}
+
+async fn get_metrics() -> impl IntoResponse {
+    let content_type = [(
+        header::CONTENT_TYPE,
+        "application/openmetrics-text; version=1.0.0; charset=utf-8",
+    )];
+
+    (content_type, metrics::encode())
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+fn router(dongle: Dongle) -> Router {
+    Router::new()
+        .route("/dongle", get(get_dongle))
+        .route("/dongle/led", post(set_led))
+        .route("/blinds", get(list_blinds))
+        .route("/blinds/{id}", delete(remove_blind))
+        .route("/blinds/{id}/{action}", post(operate_blind))
+        .route("/scenes/{name}", post(run_scene))
+        .with_state(dongle)
+}
+
+/// Serves the HTTP API of every dongle on `listen`, along with its OpenAPI document.
+///
+/// A single dongle is served at the root; several are each served under
+/// `/dongles/<serial>`. The events of all of them are streamed at `/events`, and their
+/// Prometheus metrics served at `/metrics`.
+pub async fn serve(listen: SocketAddr, dongles: Vec<Dongle>, events: Events) -> Result<()> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", listen))?;
+
+    info!(target: "http", "Serving HTTP API on {}.", listen);
+
+    Ok(axum::serve(listener, app(dongles, events)).await?)
+}
+
+/// Routes of the whole API, as served by [`serve`].
+fn app(dongles: Vec<Dongle>, events: Events) -> Router {
+    let mut app = Router::new()
+        .route("/openapi.json", get(openapi))
+        .route("/metrics", get(get_metrics));
+
+    if let [dongle] = dongles.as_slice() {
+        app = app.merge(router(dongle.clone()));
+    } else {
+        for dongle in dongles.iter().cloned() {
+            app = app.nest(&format!("/dongles/{}", dongle.serial), router(dongle));
+        }
+    }
+
+    let hub = Hub {
+        dongles: Arc::new(dongles),
+        events,
+    };
+
+    app.merge(
+        Router::new()
+            .route("/events", get(self::events))
+            .with_state(hub),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{self, Body},
+        http::{self, Method},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn dongle(serial: &str) -> Dongle {
+        Dongle {
+            serial: serial.to_string(),
+            firmware: "1.0".to_string(),
+            queue: CommandQueue::new(serial),
+            scenes: Arc::default(),
+        }
+    }
+
+    /// Sends a request through the API of `dongles`, none of which goes through its queue.
+    async fn send(
+        dongles: &[&str],
+        method: Method,
+        uri: &str,
+        json: Option<&str>,
+    ) -> (StatusCode, String) {
+        let app = app(
+            dongles.iter().map(|serial| dongle(serial)).collect(),
+            Events::new(),
+        );
+
+        let request = http::Request::builder().method(method).uri(uri);
+
+        let request = match json {
+            Some(json) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json.to_string())),
+            None => request.body(Body::empty()),
+        };
+
+        let response = app.oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn unknown_blind_is_not_found() {
+        for uri in ["/blinds/0/up", "/blinds/101/up"] {
+            let (status, body) = send(&["A"], Method::POST, uri, None).await;
+
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+            assert!(body.contains("expected 1 to 100"), "{uri}: {body}");
+        }
+
+        let (status, _) = send(&["A"], Method::DELETE, "/blinds/101", None).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn bad_blind_or_action_is_refused() {
+        for uri in ["/blinds/kitchen/up", "/blinds/256/up", "/blinds/1/open"] {
+            let (status, _) = send(&["A"], Method::POST, uri, None).await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_scene_is_not_found() {
+        let (status, body) = send(&["A"], Method::POST, "/scenes/movie", None).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("No scene movie"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn bad_led_payload_is_refused() {
+        let cases = [
+            (
+                r#"{"color": "blue", "length": 10}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (r#"{"color": "red"}"#, StatusCode::UNPROCESSABLE_ENTITY),
+            (
+                r#"{"color": "red", "length": 10, "blink": true}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                r#"{"color": "red", "length": 70000}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (r#"{"color": "red""#, StatusCode::BAD_REQUEST),
+        ];
+
+        for (json, expected) in cases {
+            let (status, _) = send(&["A"], Method::POST, "/dongle/led", Some(json)).await;
+
+            assert_eq!(status, expected, "{json}");
+        }
+    }
+
+    #[tokio::test]
+    async fn dongles_are_served_under_their_serial() {
+        let dongles = ["A", "B"];
+
+        // Routed to the dongle's own API, which checks the slot.
+        let (status, body) = send(&dongles, Method::POST, "/dongles/B/blinds/101/up", None).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("No slot 101"), "{body}");
+
+        let (status, body) = send(&dongles, Method::POST, "/dongles/C/blinds/1/up", None).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.is_empty(), "{body}");
+
+        // With several dongles, none is served at the root.
+        let (status, _) = send(&dongles, Method::POST, "/blinds/1/up", None).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -1,52 +1,135 @@
-use std::{time::{Duration, SystemTime}};
+use std::{collections::BTreeSet, net::SocketAddr, path::PathBuf, sync::Arc, time::SystemTime};
 
-use anyhow::{Result};
+use anyhow::{Result, bail};
 use clap::Parser;
-use futures::TryFutureExt;
-use log::{info, trace, debug, warn};
-use rumqttc::{AsyncClient, Event, MqttOptions, Packet};
-
-
+use futures::{future, FutureExt, TryFutureExt};
+use log::{info, debug, warn, LevelFilter};
+use rumqttc::{AsyncClient, EventLoop};
+use somfy_rts::{Ready, SomfyRTSDongle};
+
+use bridge::Bridge;
+use broker::Broker;
+use config::{Config, Transport};
+use events::Events;
+use homie::Homie;
+use somfy::DongleInfo;
+
+mod bridge;
+mod broker;
+mod command;
+mod config;
+#[cfg(unix)]
+mod control;
+mod cover;
+mod events;
 mod ha;
+mod homie;
+mod http;
+mod metrics;
+mod queue;
+mod scene;
+mod schedule;
 mod somfy;
+mod tls;
+mod topics;
+
+/// Requests pile up in the client until the event loop is polled, which only happens
+/// once every discovery message has been queued: leave room for all of them.
+const MQTT_REQUEST_CAPACITY: usize = 1024;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Serial port name to operate on. If none is provided, we will attemp to find one.
+    /// Serial port name to operate on, repeated for each dongle. If none is provided, every
+    /// dongle found is used.
     #[arg(short, long, value_name = "SERIAL PORT")]
-    serial: Option<String>,
+    serial: Vec<String>,
+
+    /// Configuration file (TOML). Command line arguments override the values it holds.
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
 
-    #[arg(value_name = "MQTT BROKER", help = "MQTT connection string in the following format: id:host:port" )]
-    #[arg(value_parser = mqtt_option)]
-    mqtt_opt: MqttOptions,
+    /// Validate the configuration, print a summary of it and exit.
+    #[arg(long)]
+    check_config: bool,
 
-    #[arg(short, long, value_name = "Optional username for auth on MQTT broker", default_value="")]
+    #[arg(
+        value_name = "MQTT BROKER",
+        help = "MQTT broker URL, such as mqtts://user@host:8883/?client_id=id&keepalive=30, \
+                or a connection string in the following format: id:host:port"
+    )]
+    mqtt_opt: Option<Broker>,
+
+    #[arg(short, long, value_name = "Optional username for auth on MQTT broker")]
     username: Option<String>,
     
-    #[arg(short, long, value_name = "Optional password for auth on MQTT broker", default_value="")]
+    #[arg(
+        short,
+        long,
+        env = "SOMFY_RTS_MQTT_PASSWORD",
+        hide_env_values = true,
+        value_name = "Optional password for auth on MQTT broker"
+    )]
     password: Option<String>,
-}
 
-fn mqtt_option(arg: &str) -> std::result::Result<MqttOptions, String> {
-    let mut parts = arg.split(':');
-
-    let id = parts
-        .next()
-        .ok_or("Missing 'id' in MQTT option string".to_string())?;
-    let host = parts
-        .next()
-        .ok_or("Missing 'host' in MQTT option string".to_string())?;
-    let port = parts
-        .next()
-        .ok_or("Missing 'port' in MQTT option string".to_string())?
-        .parse::<u16>()
-        .map_err(|e| format!("Bad 'port' value in MQTT option string: {}", e))?;
-
-    Ok(MqttOptions::new(id, host, port))
+    /// File holding the password for auth on MQTT broker
+    #[arg(long, value_name = "FILE")]
+    password_file: Option<PathBuf>,
+
+    /// Base of the topics used by the bridge, `somfy-rts` by default
+    #[arg(long, value_name = "PREFIX")]
+    topic_prefix: Option<String>,
+
+    /// How to reach the MQTT broker
+    #[arg(long, value_enum)]
+    transport: Option<Transport>,
+
+    /// Path of the broker's WebSocket endpoint, `/mqtt` by default
+    #[arg(long, value_name = "PATH")]
+    ws_path: Option<String>,
+
+    /// CA bundle (PEM) to check the broker's certificate against, instead of the system's
+    #[arg(long, value_name = "FILE")]
+    ca_file: Option<PathBuf>,
+
+    /// Client certificate (PEM), for brokers requiring client authentication
+    #[arg(long, value_name = "FILE", requires = "client_key")]
+    client_cert: Option<PathBuf>,
+
+    /// Private key (PEM) of the client certificate
+    #[arg(long, value_name = "FILE", requires = "client_cert")]
+    client_key: Option<PathBuf>,
+
+    /// Do not check the broker's certificate (for self-signed brokers)
+    #[arg(long)]
+    insecure: bool,
+
+    /// Serve the HTTP API on this address, such as 127.0.0.1:8080
+    #[arg(long, value_name = "ADDRESS")]
+    http: Option<SocketAddr>,
+
+    /// Serve the Unix socket through which `somfy-rts-cli` drives the dongles, at this path
+    #[arg(long, value_name = "PATH")]
+    control_socket: Option<PathBuf>,
+
+    /// Prefix of Home Assistant discovery topics, `homeassistant` by default
+    #[arg(long, value_name = "PREFIX")]
+    discovery_prefix: Option<String>,
+
+    /// Publish the dongle following the Homie convention too, for controllers such as openHAB
+    #[arg(long)]
+    homie: bool,
+
+    /// Record the traffic with each dongle to a capture file in this directory
+    #[arg(long, value_name = "DIR")]
+    record: Option<PathBuf>,
+
+    /// Logging level: off, error, warn, info, debug or trace
+    #[arg(short, long, value_name = "LEVEL")]
+    log_level: Option<LevelFilter>,
 }
 
-fn init_logging() -> Result<(), fern::InitError> {
+fn init_logging(level: LevelFilter) -> Result<(), fern::InitError> {
     fern::Dispatch::new()
         .format(|out, message, record| {
             out.finish(format_args!(
@@ -57,7 +140,7 @@ fn init_logging() -> Result<(), fern::InitError> {
                 message
             ))
         })
-        .level(log::LevelFilter::Debug)
+        .level(level)
         .level_for("rumqttc::state", log::LevelFilter::Error)
         .level_for("mio_serial", log::LevelFilter::Error)
         .chain(std::io::stdout())
@@ -71,97 +154,160 @@ fn init_logging() -> Result<(), fern::InitError> {
 #[tokio::main]
 async fn main() -> Result<()> {
 
-    init_logging().unwrap();
-
     let args = Cli::parse();
 
-    if let Some(port) = args.serial.as_ref() {
-        debug!(target: "main", "{:?} supplied as serial port.", port);
+    let config = Config::from_cli(&args)?;
+
+    if args.check_config {
+        return config.print_summary();
+    }
+
+    init_logging(config.log_level.unwrap_or(LevelFilter::Debug)).unwrap();
+
+    if let Some(path) = args.config.as_ref() {
+        debug!(target: "main", "Loaded configuration from '{}'.", path.display());
     }
 
-    let (mut dongle_ready, dongle_alive) =
-        somfy::get_rts_dongle(args.serial.clone()).and_then(somfy::init_dongle).await?;
+    let ports = somfy::dongle_ports(&config.serial)?;
 
-    info!(target: "main", "Successfully initialized dongle at '{:?}'.", args.serial);
+    let mut bridges = Vec::with_capacity(ports.len() + 1);
+    let mut apis = Vec::with_capacity(ports.len());
+    let mut serials = BTreeSet::new();
 
-    let mut mqttoptions = args.mqtt_opt;
-    
-    mqttoptions.set_keep_alive(Duration::from_secs(5));
+    let events = Events::new();
+
+    for port in ports.iter().cloned() {
+        debug!(target: "main", "Opening dongle at '{}'.", port);
+
+        let (dongle_ready, dongle_info) =
+            somfy::init_dongle(port, config.record.as_deref()).await?;
+
+        info!(
+            target: "main",
+            "Successfully initialized dongle {} at '{}'.",
+            dongle_info.serial(),
+            dongle_info.port
+        );
+
+        if !serials.insert(dongle_info.serial().to_string()) {
+            bail!(
+                "Dongle {} was found twice, last at '{}'",
+                dongle_info.serial(),
+                dongle_info.port
+            );
+        }
+
+        let config = config.for_dongle(dongle_info.serial(), ports.len())?;
+
+        let serial = dongle_info.serial().to_string();
+        let firmware = dongle_info.firmware.clone();
+        let scenes = config
+            .scenes
+            .keys()
+            .filter_map(|name| Some((name.clone(), config.scene(name)?)))
+            .collect();
+
+        let (bridge, eventloop, homie_eventloop) =
+            start_bridge(dongle_ready, dongle_info, config, events.clone()).await?;
 
-    if let Some(username) = args.username {
-        let password = args.password.unwrap_or_default();
+        apis.push(http::Dongle {
+            serial,
+            firmware,
+            queue: bridge.queue(),
+            scenes: Arc::new(scenes),
+        });
 
-        mqttoptions.set_credentials(username, password);
+        bridges.push(bridge.run(eventloop, homie_eventloop).boxed());
     }
 
-    let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+    for serial in config
+        .dongles
+        .keys()
+        .filter(|serial| !serials.contains(*serial))
+    {
+        warn!(target: "main", "Dongle {} is configured but was not found.", serial);
+    }
+
+    #[cfg(unix)]
+    if config.control.enabled {
+        match control::listen(&config.control.socket).await {
+            Ok(listener) => bridges.push(control::serve(listener, apis.clone()).boxed()),
+            Err(e) => warn!(target: "main", "Not serving the control socket: {:#}", e),
+        }
+    }
+
+    if let Some(listen) = config.http.listen {
+        bridges.push(http::serve(listen, apis, events).boxed());
+    }
+
+    // Bridges only stop on failure: stop as soon as any does, so that the service manager
+    // restarts all of them.
+    let (result, _, _) = future::select_all(bridges).await;
+
+    result
+}
+
+/// Lists the blinds of a dongle, announces them to Home Assistant (and as a Homie device, if
+/// enabled) along with the dongle, then hands the dongle over to its bridge.
+async fn start_bridge(
+    mut dongle_ready: SomfyRTSDongle<Ready>,
+    dongle_info: DongleInfo,
+    config: Config,
+    events: Events,
+) -> Result<(Bridge, EventLoop, Option<EventLoop>)> {
+    let topics = config.topics();
+
+    let mut mqttoptions = config.mqtt_options()?;
+
+    mqttoptions.set_last_will(ha::last_will(&topics));
+
+    let (client, eventloop) = AsyncClient::new(mqttoptions, MQTT_REQUEST_CAPACITY);
 
     let active_blinds = somfy::list_usable_blinds(&mut dongle_ready).await;
 
-    info!(target: "main", "Found {} useable blinds.", active_blinds.len());
+    info!(
+        target: "main",
+        "Found {} useable blinds on dongle {}.",
+        active_blinds.len(),
+        dongle_info.serial()
+    );
 
-    ha::setup_mqtt_autodiscovery(&client, &active_blinds, &dongle_alive.id[0])
-        .and_then(ha::set_state_on)
+    ha::setup_dongle_autodiscovery(&client, &dongle_info, &config)
+        .and_then(|client| {
+            ha::setup_mqtt_autodiscovery(client, &active_blinds, dongle_info.serial(), &config)
+        })
+        .and_then(|client| ha::setup_group_autodiscovery(client, &dongle_info, &config))
+        .and_then(|client| ha::setup_schedule_autodiscovery(client, &dongle_info, &config))
+        .and_then(|client| ha::setup_scene_autodiscovery(client, &dongle_info, &config))
+        .and_then(|client| ha::watch_discovery_configs(client, dongle_info.serial(), &config))
+        .and_then(|client| ha::set_state_on(client, &topics))
         .await?;
 
-    info!(target: "main", "Successfully set HA MQTT discovery up.");
-    
-    while let Ok(notification) = eventloop.poll().await {
-        trace!(target:"main", "Received = {:?}", notification);
-
-        if let Event::Incoming(Packet::Publish(data)) = notification {
-            debug!(target:"main", "{} -> {:?}", data.topic, data.payload);
-
-            let mut topic = data.topic.split('/');
-
-            let blind_id = topic.nth(2).map(str::parse::<u8>);
-
-            let payload = std::str::from_utf8(&data.payload);
-
-            let response = match (blind_id, payload) {
-
-                (Some(Ok(id)), Ok("UP")) => {
-                    dongle_ready
-                        .operate_blind(somfy_rts::RtsCommand::Up(id))
-                        .await
-                },
-                (Some(Ok(id)), Ok("DOWN")) => {
-                    dongle_ready
-                        .operate_blind(somfy_rts::RtsCommand::Down(id))
-                        .await
-                },
-                (Some(Ok(id)), Ok("STOP")) => {
-                    dongle_ready
-                        .operate_blind(somfy_rts::RtsCommand::Stop(id))
-                        .await
-                },
-
-                (None, _) => {
-                    warn!(target:"main", "Received message with bad topic: '{}'", data.topic);
-                    continue;
-                },
-
-                (Some(Err(e)), _) => {
-                    warn!(target:"main", "Received message with bad blind id: '{}' ({})", data.topic, e);
-                    continue;
-                },
-
-                (_, Err(e)) => {
-                    warn!(target:"main", "Received message with bad payload: {}", e);
-                    continue;
-                }
-
-                (_, Ok(o)) => {
-                    warn!(target:"main", "Received message with unknown order: {}'", o);
-                    continue;
-                }
-            };
-
-            if let Err(e) = response {
-                warn!(target:"main", "Order failed: {}", e)
-            }
-        }
-    }
+    info!(target: "main", "Successfully set HA MQTT discovery up for dongle {}.", dongle_info.serial());
 
-    Ok(())
-}
\ No newline at end of file
+    let (homie, homie_eventloop) = if config.homie.enabled {
+        let (homie, eventloop) = Homie::new(&config, dongle_info.serial())?;
+
+        let ids = active_blinds.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+
+        homie.announce(&ids, &config).await?;
+
+        info!(target: "main", "Published dongle {} as a Homie device.", dongle_info.serial());
+
+        (Some(homie), Some(eventloop))
+    } else {
+        (None, None)
+    };
+
+    let bridge = Bridge::new(
+        client,
+        dongle_ready,
+        &dongle_info,
+        &active_blinds,
+        config,
+        events,
+        homie,
+    );
+
+    Ok((bridge, eventloop, homie_eventloop))
+}
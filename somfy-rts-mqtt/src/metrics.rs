@@ -0,0 +1,236 @@
+use std::sync::LazyLock;
+
+use prometheus_client::{
+    encoding::{text, EncodeLabelSet},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use somfy_rts::{frame::command_name, Exchange};
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct DongleLabels {
+    dongle: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct CommandLabels {
+    dongle: String,
+    /// Empty for commands that are not about a blind.
+    blind: String,
+    command: String,
+}
+
+type Latency = Family<DongleLabels, Histogram, fn() -> Histogram>;
+
+/// Metrics of every dongle, served at `/metrics` along with the HTTP API.
+struct Metrics {
+    registry: Registry,
+    commands: Family<CommandLabels, Counter>,
+    dongle_ko: Family<DongleLabels, Counter>,
+    serial_errors: Family<DongleLabels, Counter>,
+    reconnects: Family<DongleLabels, Counter>,
+    latency: Latency,
+    rssi: Family<DongleLabels, Gauge>,
+    queue_depth: Family<DongleLabels, Gauge>,
+    received: Family<DongleLabels, Counter>,
+    rejected: Family<DongleLabels, Counter>,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+fn latency_histogram() -> Histogram {
+    // From 10ms to about 5s.
+    Histogram::new(exponential_buckets(0.01, 2.0, 10))
+}
+
+fn labels(dongle: &str) -> DongleLabels {
+    DongleLabels {
+        dongle: dongle.to_string(),
+    }
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let mut registry = Registry::with_prefix("somfy_rts");
+
+        let commands = Family::default();
+        let dongle_ko = Family::default();
+        let serial_errors = Family::default();
+        let reconnects = Family::default();
+        let latency = Latency::new_with_constructor(latency_histogram);
+        let rssi = Family::default();
+        let queue_depth = Family::default();
+        let received = Family::default();
+        let rejected = Family::default();
+
+        registry.register("commands", "Commands sent to the dongle", commands.clone());
+        registry.register(
+            "dongle_ko",
+            "Commands refused by the dongle (DONGLE_KO)",
+            dongle_ko.clone(),
+        );
+        registry.register(
+            "serial_errors",
+            "Commands the dongle could not be reached for",
+            serial_errors.clone(),
+        );
+        registry.register(
+            "reconnects",
+            "Times the dongle was opened again after a reboot",
+            reconnects.clone(),
+        );
+        registry.register(
+            "command_latency_seconds",
+            "Time taken by the dongle to answer a command",
+            latency.clone(),
+        );
+        registry.register(
+            "rssi",
+            "Signal strength, as measured by the dongle",
+            rssi.clone(),
+        );
+        registry.register(
+            "queue_depth",
+            "Requests waiting for the dongle",
+            queue_depth.clone(),
+        );
+        registry.register("mqtt_received", "MQTT messages received", received.clone());
+        registry.register(
+            "mqtt_rejected",
+            "MQTT messages received that could not be acted upon",
+            rejected.clone(),
+        );
+
+        Metrics {
+            registry,
+            commands,
+            dongle_ko,
+            serial_errors,
+            reconnects,
+            latency,
+            rssi,
+            queue_depth,
+            received,
+            rejected,
+        }
+    }
+}
+
+/// Counts a command that went through dongle `dongle`, as soon as it did.
+pub fn command(dongle: &str, exchange: &Exchange) {
+    let command_labels = CommandLabels {
+        dongle: dongle.to_string(),
+        blind: exchange.blind.map(|id| id.to_string()).unwrap_or_default(),
+        command: command_name(&exchange.command),
+    };
+
+    METRICS.commands.get_or_create(&command_labels).inc();
+
+    match exchange
+        .answer
+        .as_ref()
+        .map(|answer| answer["ACK"].as_str())
+    {
+        Ok(Some("DONGLE_KO")) => {
+            METRICS.dongle_ko.get_or_create(&labels(dongle)).inc();
+        }
+
+        Ok(Some(_)) => {}
+
+        Ok(None) | Err(_) => {
+            METRICS.serial_errors.get_or_create(&labels(dongle)).inc();
+        }
+    }
+
+    METRICS
+        .latency
+        .get_or_create(&labels(dongle))
+        .observe(exchange.latency.as_secs_f64());
+}
+
+/// Counts dongle `dongle` being opened again after a reboot.
+pub fn reconnected(dongle: &str) {
+    METRICS.reconnects.get_or_create(&labels(dongle)).inc();
+}
+
+pub fn mqtt_received(dongle: &str) {
+    METRICS.received.get_or_create(&labels(dongle)).inc();
+}
+
+pub fn mqtt_rejected(dongle: &str) {
+    METRICS.rejected.get_or_create(&labels(dongle)).inc();
+}
+
+pub fn rssi(dongle: &str, rssi: i32) {
+    METRICS.rssi.get_or_create(&labels(dongle)).set(rssi.into());
+}
+
+/// Number of requests waiting for dongle `dongle`.
+pub fn queue_depth(dongle: &str, depth: usize) {
+    METRICS
+        .queue_depth
+        .get_or_create(&labels(dongle))
+        .set(depth.try_into().unwrap_or(i64::MAX));
+}
+
+/// Every metric, in the Prometheus text format.
+pub fn encode() -> String {
+    let mut buffer = String::new();
+
+    // Writing to a `String` does not fail.
+    text::encode(&mut buffer, &METRICS.registry).unwrap();
+
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn refused_command_is_counted() {
+        let exchange = Exchange {
+            command: json!({ "CMD-RTS": ["UP", 1] }),
+            blind: Some(1),
+            answer: Ok(json!({ "ACK": "DONGLE_KO" })),
+            latency: Duration::from_millis(20),
+        };
+
+        command("REFUSING", &exchange);
+
+        let metrics = encode();
+
+        assert!(metrics
+            .contains(r#"somfy_rts_commands_total{dongle="REFUSING",blind="1",command="UP"} 1"#));
+        assert!(metrics.contains(r#"somfy_rts_dongle_ko_total{dongle="REFUSING"} 1"#));
+        assert!(!metrics.contains(r#"somfy_rts_serial_errors_total{dongle="REFUSING"}"#));
+    }
+
+    #[test]
+    fn unanswered_command_is_counted() {
+        let exchange = Exchange {
+            command: json!({ "CMD-DONGLE": "ALIVE" }),
+            blind: None,
+            answer: Err("Timed out".to_string()),
+            latency: Duration::from_secs(1),
+        };
+
+        command("UNREACHABLE", &exchange);
+
+        let metrics = encode();
+
+        assert!(metrics.contains(
+            r#"somfy_rts_commands_total{dongle="UNREACHABLE",blind="",command="ALIVE"} 1"#
+        ));
+        assert!(metrics.contains(r#"somfy_rts_serial_errors_total{dongle="UNREACHABLE"} 1"#));
+    }
+}
@@ -0,0 +1,721 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::{debug, error, info, warn};
+use somfy_rts::{
+    scene::Action, AddressVal, Alive, LedAction, LedColor, Ready, Response, RtsCommand,
+    SomfyRTSDongle,
+};
+use tokio::{
+    sync::{mpsc, oneshot, Notify},
+    time,
+};
+
+use crate::{
+    cover::Pulse,
+    events::{Connection, Events},
+    metrics,
+    somfy::{self, DongleInfo},
+};
+
+/// Time left to the dongle to come back after a reboot, before we open it again.
+const REBOOT_DELAY: Duration = Duration::from_secs(5);
+
+/// Longest wait between two attempts at opening the dongle again, when it does not come back.
+const MAX_REOPEN_DELAY: Duration = Duration::from_secs(300);
+
+/// Something to be done by the dongle.
+#[derive(Debug, Clone)]
+pub enum Request {
+    Operate(RtsCommand),
+
+    /// The same frame sent several times over, for objects that are hard to reach.
+    Repeat(RtsCommand, u8),
+
+    /// Short presses on UP or DOWN, each followed by STOP, to tilt the slats of a venetian
+    /// blind to an angle.
+    Tilt(u8, f64, Vec<Pulse>),
+
+    /// Pairs a blind in programming mode with an empty slot.
+    Pair(u8),
+
+    /// Resets a slot, so the dongle no longer commands the blind it held.
+    Unpair(u8),
+
+    Alive,
+
+    /// Reads the address and rolling code held in a slot.
+    GetBlind(u8),
+
+    /// Lists the slots holding a blind, querying them one by one.
+    ListBlinds,
+
+    Led(LedColor, LedAction, u16),
+
+    Reboot,
+}
+
+#[derive(Debug, Clone)]
+pub enum Reply {
+    Blind(AddressVal),
+    Blinds(Vec<(u8, AddressVal)>),
+    Alive(Alive),
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub enum Failure {
+    /// The dongle answered with `DONGLE_KO`.
+    Refused(String),
+
+    /// We could not talk to the dongle.
+    Error(String),
+
+    /// A newer request for the same blind replaced this one before it was sent.
+    Superseded,
+}
+
+pub type Outcome = Result<Reply, Failure>;
+
+/// Emitted by the worker for every request it went through.
+pub struct Completion {
+    pub request: Request,
+    pub outcome: Outcome,
+}
+
+struct Job {
+    request: Request,
+    reply: oneshot::Sender<Outcome>,
+}
+
+struct Shared {
+    /// Serial number of the dongle, which the queue depth is measured for.
+    serial: String,
+    jobs: Mutex<VecDeque<Job>>,
+    notify: Notify,
+    /// Generation of the follow-up order waiting for each blind. Dropping the sender
+    /// cancels it.
+    follow_ups: Mutex<BTreeMap<u8, (u64, oneshot::Sender<()>)>>,
+    generation: AtomicU64,
+}
+
+/// A follow-up order waiting for its delay to elapse, told apart from the ones registered
+/// for the same blind before and after it.
+pub struct FollowUp {
+    id: u8,
+    generation: u64,
+
+    /// Resolves once the follow-up is cancelled by a newer request for the blind.
+    pub cancelled: oneshot::Receiver<()>,
+}
+
+/// Requests waiting for the dongle, which only ever handles one at a time.
+///
+/// An order moving a blind drops any such order for the same blind that is still pending,
+/// so that bursts of commands do not each burn a rolling code. Other requests, such as
+/// pairing, always go through.
+///
+/// Any request for a blind cancels the follow-up order that may be waiting for it.
+#[derive(Clone)]
+pub struct CommandQueue {
+    shared: Arc<Shared>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Refused(e) => write!(f, "Refused by dongle: {}", e),
+            Self::Error(e) => write!(f, "{}", e),
+            Self::Superseded => write!(f, "Superseded by a newer command"),
+        }
+    }
+}
+
+impl Request {
+    /// The blind this request is about, if any.
+    pub fn blind(&self) -> Option<u8> {
+        match self {
+            Self::Operate(command) | Self::Repeat(command, _) => Some(target(command)),
+
+            Self::Tilt(id, ..) | Self::Pair(id) | Self::Unpair(id) => Some(*id),
+
+            // Reading a slot neither replaces nor gets replaced by an order for its blind.
+            Self::Alive | Self::GetBlind(_) | Self::ListBlinds | Self::Led(..) | Self::Reboot => {
+                None
+            }
+        }
+    }
+
+    /// The blind this request moves, if it is a plain order that a newer one may replace.
+    fn movement(&self) -> Option<u8> {
+        match self {
+            Self::Operate(command) | Self::Repeat(command, _) => match command {
+                RtsCommand::Up(id)
+                | RtsCommand::Down(id)
+                | RtsCommand::Stop(id)
+                | RtsCommand::My(id) => Some(*id),
+
+                RtsCommand::Prog(_) | RtsCommand::ProgRt(_) | RtsCommand::FourCycles(_) => None,
+            },
+
+            _ => None,
+        }
+    }
+}
+
+/// The slot a command is sent from.
+fn target(command: &RtsCommand) -> u8 {
+    match command {
+        RtsCommand::Up(id)
+        | RtsCommand::Down(id)
+        | RtsCommand::Prog(id)
+        | RtsCommand::My(id)
+        | RtsCommand::Stop(id)
+        | RtsCommand::ProgRt(id)
+        | RtsCommand::FourCycles(id) => *id,
+    }
+}
+
+/// Removes a follow-up from those registered, returning whether it still was.
+fn take_follow_up(
+    follow_ups: &mut BTreeMap<u8, (u64, oneshot::Sender<()>)>,
+    follow_up: &FollowUp,
+) -> bool {
+    match follow_ups.get(&follow_up.id) {
+        Some((generation, _)) if *generation == follow_up.generation => {
+            follow_ups.remove(&follow_up.id);
+            true
+        }
+
+        _ => false,
+    }
+}
+
+fn acknowledged<T>(response: Response<T>) -> Result<T, Failure> {
+    match response {
+        Response::DongleOk(t) => Ok(t),
+        Response::Err(e) => Err(Failure::Refused(e)),
+    }
+}
+
+impl CommandQueue {
+    /// An empty queue for the dongle reporting `serial`, which only a worker goes through.
+    pub fn new(serial: &str) -> Self {
+        CommandQueue {
+            shared: Arc::new(Shared {
+                serial: serial.to_string(),
+                jobs: Mutex::new(VecDeque::new()),
+                notify: Notify::new(),
+                follow_ups: Mutex::new(BTreeMap::new()),
+                generation: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Queues a request; the receiver resolves once the dongle went through it.
+    pub fn push(&self, request: Request) -> oneshot::Receiver<Outcome> {
+        // Held until the request is queued, for a follow-up not to slip in after it.
+        let mut follow_ups = self.shared.follow_ups.lock().unwrap();
+
+        if let Some(id) = request.blind() {
+            follow_ups.remove(&id);
+        }
+
+        self.enqueue(request)
+    }
+
+    fn enqueue(&self, request: Request) -> oneshot::Receiver<Outcome> {
+        let (reply, receiver) = oneshot::channel();
+
+        let job = Job { request, reply };
+
+        let mut jobs = self.shared.jobs.lock().unwrap();
+
+        // The newer order goes to the back of the queue, behind requests for the blind that
+        // were queued after the order it replaces.
+        let superseded = job.request.movement().and_then(|id| {
+            jobs.iter()
+                .position(|pending| pending.request.movement() == Some(id))
+        });
+
+        if let Some(pending) = superseded.and_then(|index| jobs.remove(index)) {
+            debug!(target:"queue", "{:?} supersedes {:?}", job.request, pending.request);
+
+            let _ = pending.reply.send(Err(Failure::Superseded));
+        }
+
+        jobs.push_back(job);
+
+        metrics::queue_depth(&self.shared.serial, jobs.len());
+
+        drop(jobs);
+
+        self.shared.notify.notify_one();
+
+        receiver
+    }
+
+    /// Runs the actions of a scene through the queue, stopping at the first request that fails.
+    pub async fn run_scene(&self, actions: &[Action]) -> Result<(), Failure> {
+        for action in actions {
+            match action {
+                Action::Send(command) => match self.push(Request::Operate(*command)).await {
+                    Ok(outcome) => drop(outcome?),
+                    Err(_) => return Err(Failure::Error("Lost the dongle".to_string())),
+                },
+
+                Action::Wait(duration) => time::sleep(*duration).await,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Registers a follow-up order for a blind, in place of the one it may already have.
+    pub fn follow_up(&self, id: u8) -> FollowUp {
+        let (cancel, cancelled) = oneshot::channel();
+
+        let generation = self.shared.generation.fetch_add(1, Ordering::Relaxed);
+
+        self.shared
+            .follow_ups
+            .lock()
+            .unwrap()
+            .insert(id, (generation, cancel));
+
+        FollowUp {
+            id,
+            generation,
+            cancelled,
+        }
+    }
+
+    /// Forgets a follow-up that will not be sent, unless a newer one took its place.
+    pub fn follow_up_done(&self, follow_up: FollowUp) {
+        take_follow_up(&mut self.shared.follow_ups.lock().unwrap(), &follow_up);
+    }
+
+    /// Queues the order of a follow-up whose delay elapsed, unless a newer request for the
+    /// blind cancelled it in the meantime.
+    ///
+    /// Unlike other requests, the order leaves alone the follow-up registered after it.
+    pub fn push_follow_up(
+        &self,
+        follow_up: FollowUp,
+        request: Request,
+    ) -> Option<oneshot::Receiver<Outcome>> {
+        let mut follow_ups = self.shared.follow_ups.lock().unwrap();
+
+        take_follow_up(&mut follow_ups, &follow_up).then(|| self.enqueue(request))
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.jobs.lock().unwrap().len()
+    }
+
+    fn try_pop(&self) -> Option<Job> {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+
+        let job = jobs.pop_front()?;
+
+        metrics::queue_depth(&self.shared.serial, jobs.len());
+
+        Some(job)
+    }
+
+    async fn pop(&self) -> Job {
+        loop {
+            if let Some(job) = self.try_pop() {
+                return job;
+            }
+
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+struct Worker {
+    dongle: SomfyRTSDongle<Ready>,
+    port: String,
+    serial: String,
+    events: Events,
+    spacing: Duration,
+    last_frame: Option<Instant>,
+    record: Option<PathBuf>,
+}
+
+/// Hands the dongle over to a worker task, which goes through queued requests one by one,
+/// leaving at least `spacing` between two radio frames.
+///
+/// After a reboot, the worker keeps trying to open the dongle again until it is back. Every
+/// command it sends, and every change of the dongle's state, goes to `events`.
+pub fn spawn(
+    mut dongle: SomfyRTSDongle<Ready>,
+    info: &DongleInfo,
+    spacing: Duration,
+    record: Option<PathBuf>,
+    events: Events,
+) -> (CommandQueue, mpsc::UnboundedReceiver<Completion>) {
+    let queue = CommandQueue::new(info.serial());
+
+    let (completions, receiver) = mpsc::unbounded_channel();
+
+    dongle.observe(events.observer(info.serial()));
+
+    events.connection(info.serial(), Connection::Online);
+
+    let worker = Worker {
+        dongle,
+        port: info.port.clone(),
+        serial: info.serial().to_string(),
+        events,
+        spacing,
+        last_frame: None,
+        record,
+    };
+
+    tokio::spawn(worker.run(queue.clone(), completions));
+
+    (queue, receiver)
+}
+
+impl Worker {
+    async fn run(mut self, queue: CommandQueue, completions: mpsc::UnboundedSender<Completion>) {
+        loop {
+            let Job { request, reply } = queue.pop().await;
+
+            let outcome = match request {
+                // The worker is taken apart, for the port to be closed before it is reopened.
+                Request::Reboot => {
+                    self = self.reboot().await;
+                    Ok(Reply::Done)
+                }
+
+                _ => self.execute(&request).await,
+            };
+
+            let _ = reply.send(outcome.clone());
+
+            if completions.send(Completion { request, outcome }).is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn execute(&mut self, request: &Request) -> Outcome {
+        match request {
+            Request::Operate(command) => {
+                self.wait_for_spacing().await;
+                self.operate(*command).await.map(Reply::Blind)
+            }
+
+            Request::Repeat(command, times) => {
+                self.repeat(*command, *times).await.map(Reply::Blind)
+            }
+
+            Request::Tilt(id, _, pulses) => {
+                self.wait_for_spacing().await;
+                self.tilt(*id, pulses).await.map(|_| Reply::Done)
+            }
+
+            Request::Pair(id) => {
+                self.wait_for_spacing().await;
+                self.pair(*id).await.map(Reply::Blind)
+            }
+
+            Request::Unpair(id) => match self.dongle.remove_blind(*id).await {
+                Ok(response) => acknowledged(response).map(|_| Reply::Done),
+                Err(e) => Err(Failure::Error(e.to_string())),
+            },
+
+            Request::Alive => match self.dongle.test_alive().await {
+                Ok(response) => acknowledged(response).map(Reply::Alive),
+                Err(e) => Err(Failure::Error(e.to_string())),
+            },
+
+            Request::GetBlind(id) => match self.dongle.get_blind(*id).await {
+                Ok(response) => acknowledged(response).map(Reply::Blind),
+                Err(e) => Err(Failure::Error(e.to_string())),
+            },
+
+            Request::ListBlinds => Ok(Reply::Blinds(
+                somfy::list_usable_blinds(&mut self.dongle).await,
+            )),
+
+            Request::Led(color, action, duration) => {
+                match self.dongle.led(*color, *action, *duration).await {
+                    Ok(response) => acknowledged(response).map(|_| Reply::Done),
+                    Err(e) => Err(Failure::Error(e.to_string())),
+                }
+            }
+
+            Request::Reboot => unreachable!("Reboots are run by the worker loop"),
+        }
+    }
+
+    async fn wait_for_spacing(&mut self) {
+        if let Some(last_frame) = self.last_frame {
+            time::sleep_until((last_frame + self.spacing).into()).await;
+        }
+    }
+
+    async fn operate(&mut self, command: RtsCommand) -> Result<AddressVal, Failure> {
+        let response = self.dongle.operate_blind(command).await;
+
+        self.last_frame = Some(Instant::now());
+
+        match response {
+            Ok(response) => acknowledged(response),
+            Err(e) => Err(Failure::Error(e.to_string())),
+        }
+    }
+
+    /// Sends the same frame `times` over, each one waiting for the previous one to be spaced.
+    async fn repeat(&mut self, command: RtsCommand, times: u8) -> Result<AddressVal, Failure> {
+        let mut blind = None;
+
+        for _ in 0..times {
+            self.wait_for_spacing().await;
+
+            blind = Some(self.operate(command).await?);
+        }
+
+        blind.ok_or(Failure::Error("Nothing to send".to_string()))
+    }
+
+    async fn tilt(&mut self, id: u8, pulses: &[Pulse]) -> Result<(), Failure> {
+        for Pulse { opening, duration } in pulses {
+            let command = if *opening {
+                RtsCommand::Up(id)
+            } else {
+                RtsCommand::Down(id)
+            };
+
+            self.operate(command).await?;
+
+            time::sleep(*duration).await;
+
+            self.operate(RtsCommand::Stop(id)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends PROG from an empty slot, then reads back the address the dongle gave it.
+    async fn pair(&mut self, id: u8) -> Result<AddressVal, Failure> {
+        self.operate(RtsCommand::Prog(id)).await?;
+
+        match self.dongle.get_blind(id).await {
+            Ok(response) => acknowledged(response),
+            Err(e) => Err(Failure::Error(e.to_string())),
+        }
+    }
+
+    /// Reboots the dongle, then goes through the whole initialization again once it is back,
+    /// trying less and less often for as long as it is not.
+    async fn reboot(mut self) -> Self {
+        info!(target:"queue", "Rebooting dongle at '{}'.", self.port);
+
+        self.events.connection(&self.serial, Connection::Rebooting);
+
+        if let Err(e) = self.dongle.reboot().await {
+            warn!(target:"queue", "Reboot order failed: {}", e);
+        }
+
+        // The dongle comes back under the same name: the old handle must not hold the port.
+        drop(self.dongle);
+
+        let mut delay = REBOOT_DELAY;
+
+        let mut dongle = loop {
+            time::sleep(delay).await;
+
+            match somfy::init_dongle(self.port.clone(), self.record.as_deref()).await {
+                Ok((dongle, _)) => break dongle,
+
+                Err(e) => {
+                    delay = (delay * 2).min(MAX_REOPEN_DELAY);
+
+                    error!(
+                        target:"queue",
+                        "Failed to reopen dongle at '{}', trying again in {}: {:#}",
+                        self.port,
+                        humantime::format_duration(delay),
+                        e
+                    );
+
+                    self.events.connection(&self.serial, Connection::Offline);
+                }
+            }
+        };
+
+        dongle.observe(self.events.observer(&self.serial));
+
+        self.dongle = dongle;
+
+        metrics::reconnected(&self.serial);
+
+        self.events.connection(&self.serial, Connection::Online);
+
+        info!(target:"queue", "Dongle at '{}' is back after reboot.", self.port);
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot::error::TryRecvError;
+
+    use super::*;
+
+    #[test]
+    fn newer_order_supersedes_pending_one() {
+        let queue = CommandQueue::new("TEST");
+
+        let mut up = queue.push(Request::Operate(RtsCommand::Up(5)));
+        let mut down = queue.push(Request::Repeat(RtsCommand::Down(5), 3));
+
+        assert_eq!(queue.len(), 1);
+        assert!(matches!(up.try_recv(), Ok(Err(Failure::Superseded))));
+        assert!(matches!(down.try_recv(), Err(TryRecvError::Empty)));
+    }
+
+    #[test]
+    fn queue_depth_is_measured() {
+        let queue = CommandQueue::new("DEPTH");
+
+        let _up = queue.push(Request::Operate(RtsCommand::Up(5)));
+        let _down = queue.push(Request::Operate(RtsCommand::Down(6)));
+        let _stop = queue.push(Request::Operate(RtsCommand::Stop(6)));
+
+        assert!(metrics::encode().contains(r#"somfy_rts_queue_depth{dongle="DEPTH"} 2"#));
+    }
+
+    #[test]
+    fn orders_for_other_blinds_are_kept() {
+        let queue = CommandQueue::new("TEST");
+
+        let _up = queue.push(Request::Operate(RtsCommand::Up(5)));
+        let _down = queue.push(Request::Operate(RtsCommand::Down(6)));
+
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn order_does_not_supersede_unpairing() {
+        let queue = CommandQueue::new("TEST");
+
+        let mut unpair = queue.push(Request::Unpair(5));
+        let _up = queue.push(Request::Operate(RtsCommand::Up(5)));
+
+        assert_eq!(queue.len(), 2);
+        assert!(matches!(unpair.try_recv(), Err(TryRecvError::Empty)));
+    }
+
+    #[test]
+    fn order_does_not_supersede_pairing_or_tilting() {
+        let queue = CommandQueue::new("TEST");
+
+        let _pair = queue.push(Request::Pair(5));
+        let _prog = queue.push(Request::Operate(RtsCommand::Prog(6)));
+        let _tilt = queue.push(Request::Tilt(7, 50.0, Vec::new()));
+
+        let _up = queue.push(Request::Operate(RtsCommand::Up(5)));
+        let _down = queue.push(Request::Operate(RtsCommand::Down(6)));
+        let _stop = queue.push(Request::Operate(RtsCommand::Stop(7)));
+
+        assert_eq!(queue.len(), 6);
+    }
+
+    #[test]
+    fn newer_order_stays_behind_tilting_and_pairing() {
+        let queue = CommandQueue::new("TEST");
+
+        let mut up = queue.push(Request::Operate(RtsCommand::Up(5)));
+        let _tilt = queue.push(Request::Tilt(5, 50.0, Vec::new()));
+        let _pair = queue.push(Request::Pair(5));
+        let _down = queue.push(Request::Operate(RtsCommand::Down(5)));
+
+        assert!(matches!(up.try_recv(), Ok(Err(Failure::Superseded))));
+
+        let requests = queue.shared.jobs.lock().unwrap();
+        let requests = requests.iter().map(|job| &job.request).collect::<Vec<_>>();
+
+        assert!(matches!(
+            requests[..],
+            [
+                Request::Tilt(5, ..),
+                Request::Pair(5),
+                Request::Operate(RtsCommand::Down(5))
+            ]
+        ));
+    }
+
+    #[test]
+    fn request_for_blind_cancels_its_follow_up() {
+        let queue = CommandQueue::new("TEST");
+
+        let mut cancelled = queue.follow_up(5);
+        let mut other = queue.follow_up(6);
+
+        let _unpair = queue.push(Request::Unpair(5));
+
+        assert_eq!(cancelled.cancelled.try_recv(), Err(TryRecvError::Closed));
+        assert_eq!(other.cancelled.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn follow_up_is_forgotten_once_done() {
+        let queue = CommandQueue::new("TEST");
+
+        let follow_up = queue.follow_up(5);
+        queue.follow_up_done(follow_up);
+
+        assert!(queue.shared.follow_ups.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn newer_follow_up_is_kept() {
+        let queue = CommandQueue::new("TEST");
+
+        let mut older = queue.follow_up(5);
+        let mut newer = queue.follow_up(5);
+
+        assert_eq!(older.cancelled.try_recv(), Err(TryRecvError::Closed));
+
+        queue.follow_up_done(older);
+
+        assert_eq!(newer.cancelled.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn late_follow_up_leaves_newer_one_alone() {
+        let queue = CommandQueue::new("TEST");
+
+        // The delay of the older follow-up elapsed just as a newer command came in.
+        let older = queue.follow_up(5);
+        let _down = queue.push(Request::Operate(RtsCommand::Down(5)));
+        let mut newer = queue.follow_up(5);
+
+        assert!(queue
+            .push_follow_up(older, Request::Operate(RtsCommand::Stop(5)))
+            .is_none());
+        assert_eq!(queue.len(), 1);
+        assert_eq!(newer.cancelled.try_recv(), Err(TryRecvError::Empty));
+
+        assert!(queue
+            .push_follow_up(newer, Request::Operate(RtsCommand::Stop(5)))
+            .is_some());
+        assert!(queue.shared.follow_ups.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+//! Scenes as declared in the configuration file. The CLI reads the same file, and includes this
+//! module as is.
+
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use serde::Deserialize;
+use somfy_rts::{
+    scene::{Action, Move},
+    RtsCommand,
+};
+
+/// An order sent to a blind, or to every member of a group.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub blind: Option<u8>,
+
+    pub group: Option<String>,
+
+    pub command: Move,
+
+    /// Pause before the order is sent.
+    #[serde(default, with = "humantime_serde")]
+    pub delay: Option<Duration>,
+
+    /// Sends STOP once this long elapsed, to leave the blinds half-way.
+    #[serde(default, with = "humantime_serde")]
+    pub stop_after: Option<Duration>,
+}
+
+/// Steps run one after the other.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    pub steps: Vec<Step>,
+}
+
+/// Why a step doesn't resolve to any blind; steps are numbered from 1.
+#[derive(Debug)]
+pub enum StepError {
+    NoTarget(usize),
+    BadBlind(usize, u8),
+    UnknownGroup(usize, String),
+}
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoTarget(step) => write!(f, "Step {step}: give either a blind or a group"),
+            Self::BadBlind(step, id) => write!(
+                f,
+                "Step {step}: `{id}` isn't a valid blind id, expected 1 to 100"
+            ),
+            Self::UnknownGroup(step, group) => write!(f, "Step {step}: unknown group `{group}`"),
+        }
+    }
+}
+
+impl std::error::Error for StepError {}
+
+impl Scene {
+    /// Resolves the scene into the commands to send, in order, groups standing for each of
+    /// their members.
+    pub fn actions(&self, groups: &BTreeMap<String, Vec<u8>>) -> Result<Vec<Action>, StepError> {
+        let mut actions = Vec::new();
+
+        for (i, step) in self.steps.iter().enumerate() {
+            let number = i + 1;
+
+            let blinds = match (step.blind, step.group.as_ref()) {
+                (Some(id @ 1..=100), None) => vec![id],
+
+                (Some(id), None) => return Err(StepError::BadBlind(number, id)),
+
+                (None, Some(group)) => groups
+                    .get(group)
+                    .cloned()
+                    .ok_or_else(|| StepError::UnknownGroup(number, group.clone()))?,
+
+                _ => return Err(StepError::NoTarget(number)),
+            };
+
+            if let Some(delay) = step.delay {
+                actions.push(Action::Wait(delay));
+            }
+
+            actions.extend(blinds.iter().map(|id| Action::Send(step.command.rts(*id))));
+
+            if let Some(stop_after) = step.stop_after {
+                actions.push(Action::Wait(stop_after));
+                actions.extend(blinds.iter().map(|id| Action::Send(RtsCommand::Stop(*id))));
+            }
+        }
+
+        Ok(actions)
+    }
+}
@@ -0,0 +1,303 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Days, Local, TimeDelta};
+use log::warn;
+use sunrise::{Coordinates, SolarDay, SolarEvent};
+
+use crate::{
+    command::Order,
+    config::{Config, Location, ScheduleConfig, Sun},
+};
+
+/// Days looked ahead for a sun event, for places where the sun may not rise for months.
+const SUN_LOOKAHEAD: u64 = 366;
+
+/// When a rule is due.
+#[derive(Clone)]
+enum Trigger {
+    Cron(cron::Schedule),
+
+    Sun {
+        event: SolarEvent,
+        coordinates: Coordinates,
+        offset: TimeDelta,
+    },
+}
+
+/// An order sent to blinds and groups at set times.
+#[derive(Clone)]
+pub struct Rule {
+    pub name: String,
+    pub command: Order,
+    pub blinds: Vec<u8>,
+    pub groups: Vec<String>,
+    pub enabled: bool,
+    trigger: Trigger,
+    next: Option<DateTime<Local>>,
+}
+
+/// Every rule of a dongle, along with when each one is next due.
+pub struct Scheduler {
+    rules: Vec<Rule>,
+}
+
+/// Parses a cron expression, with or without its leading seconds field.
+pub fn parse_cron(expression: &str) -> Result<cron::Schedule, cron::error::Error> {
+    match expression.split_whitespace().count() {
+        5 => cron::Schedule::from_str(&format!("0 {}", expression)),
+        _ => cron::Schedule::from_str(expression),
+    }
+}
+
+impl Trigger {
+    fn new(schedule: &ScheduleConfig, location: Option<Location>) -> Option<Self> {
+        if let Some(expression) = schedule.cron.as_deref() {
+            return parse_cron(expression).ok().map(Trigger::Cron);
+        }
+
+        let Location {
+            latitude,
+            longitude,
+        } = location?;
+
+        Some(Trigger::Sun {
+            event: match schedule.sun? {
+                Sun::Sunrise => SolarEvent::Sunrise,
+                Sun::Sunset => SolarEvent::Sunset,
+            },
+            coordinates: Coordinates::new(latitude, longitude)?,
+            offset: schedule.offset,
+        })
+    }
+
+    /// First time the trigger fires after `now`.
+    fn after(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Trigger::Cron(schedule) => schedule.after(&now).next(),
+
+            Trigger::Sun {
+                event,
+                coordinates,
+                offset,
+            } => (0..=SUN_LOOKAHEAD)
+                .filter_map(|days| now.date_naive().checked_add_days(Days::new(days)))
+                .filter_map(|date| SolarDay::new(*coordinates, date).event_time(*event))
+                .map(|time| time.with_timezone(&Local) + *offset)
+                .find(|time| *time > now),
+        }
+    }
+}
+
+impl Rule {
+    pub fn next(&self) -> Option<DateTime<Local>> {
+        self.next.filter(|_| self.enabled)
+    }
+}
+
+impl Scheduler {
+    pub fn new(config: &Config) -> Self {
+        let now = Local::now();
+
+        let rules = config
+            .schedules
+            .iter()
+            .filter_map(|(name, schedule)| {
+                let Some(trigger) = Trigger::new(schedule, config.location) else {
+                    warn!(target:"schedule", "Schedule '{}' cannot run, skipping it", name);
+                    return None;
+                };
+
+                let next = trigger.after(now);
+
+                if next.is_none() {
+                    warn!(target:"schedule", "Schedule '{}' will never run", name);
+                }
+
+                Some(Rule {
+                    name: name.clone(),
+                    command: schedule.command,
+                    blinds: schedule.blinds.clone(),
+                    groups: schedule.groups.clone(),
+                    enabled: schedule.enabled,
+                    trigger,
+                    next,
+                })
+            })
+            .collect();
+
+        Scheduler { rules }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// When the earliest enabled rule is due, if any is.
+    pub fn next(&self) -> Option<DateTime<Local>> {
+        self.rules.iter().filter_map(Rule::next).min()
+    }
+
+    /// Turns a rule on or off, returning it if there is one by that name.
+    pub fn enable(&mut self, name: &str, enabled: bool) -> Option<&Rule> {
+        let rule = self.rules.iter_mut().find(|rule| rule.name == name)?;
+
+        // Runs missed while the rule was off are not caught up.
+        if enabled && !rule.enabled {
+            rule.next = rule.trigger.after(Local::now());
+        }
+
+        rule.enabled = enabled;
+
+        Some(rule)
+    }
+
+    /// Takes the rules due by `now`, moving each one to its next run.
+    pub fn due(&mut self, now: DateTime<Local>) -> Vec<Rule> {
+        let mut due = Vec::new();
+
+        for rule in self.rules.iter_mut() {
+            let Some(next) = rule.next else {
+                continue;
+            };
+
+            if next > now {
+                continue;
+            }
+
+            rule.next = rule.trigger.after(now);
+
+            if rule.enabled {
+                due.push(rule.clone());
+            }
+        }
+
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Datelike, TimeZone, Utc};
+
+    use super::*;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn sun(event: SolarEvent, latitude: f64, longitude: f64, offset: TimeDelta) -> Trigger {
+        Trigger::Sun {
+            event,
+            coordinates: Coordinates::new(latitude, longitude).unwrap(),
+            offset,
+        }
+    }
+
+    fn cron(expression: &str) -> Trigger {
+        Trigger::Cron(parse_cron(expression).unwrap())
+    }
+
+    #[test]
+    fn cron_without_seconds_fires_on_the_minute() {
+        // A Monday.
+        let now = local(2024, 3, 4, 6, 30);
+
+        assert_eq!(
+            cron("0 7 * * Mon-Fri").after(now),
+            Some(local(2024, 3, 4, 7, 0))
+        );
+    }
+
+    #[test]
+    fn cron_with_seconds_is_taken_as_is() {
+        let now = local(2024, 3, 4, 6, 30);
+
+        assert_eq!(
+            cron("30 0 7 * * Mon-Fri").after(now),
+            Some(local(2024, 3, 4, 7, 0) + TimeDelta::seconds(30))
+        );
+    }
+
+    #[test]
+    fn cron_skips_to_next_matching_day() {
+        // A Friday, after the blinds were opened.
+        let now = local(2024, 3, 8, 8, 0);
+
+        assert_eq!(
+            cron("0 7 * * Mon-Fri").after(now),
+            Some(local(2024, 3, 11, 7, 0))
+        );
+
+        // Not due again at the very time it fired.
+        assert_eq!(
+            cron("0 7 * * *").after(local(2024, 3, 8, 7, 0)),
+            Some(local(2024, 3, 9, 7, 0))
+        );
+    }
+
+    #[test]
+    fn bad_cron_is_refused() {
+        assert!(parse_cron("0 7 * *").is_err());
+        assert!(parse_cron("0 25 * * *").is_err());
+    }
+
+    #[test]
+    fn sunset_in_paris() {
+        let trigger = sun(SolarEvent::Sunset, 48.85, 2.35, TimeDelta::zero());
+
+        let sunset = trigger
+            .after(local(2024, 6, 21, 0, 0))
+            .unwrap()
+            .with_timezone(&Utc);
+
+        // Sunset on the summer solstice is around 21:58 in Paris (19:58 UTC).
+        assert!(sunset > Utc.with_ymd_and_hms(2024, 6, 21, 19, 50, 0).unwrap());
+        assert!(sunset < Utc.with_ymd_and_hms(2024, 6, 21, 20, 5, 0).unwrap());
+    }
+
+    #[test]
+    fn negative_offset_fires_before_sun_event() {
+        let now = local(2024, 6, 21, 0, 0);
+
+        let sunset = sun(SolarEvent::Sunset, 48.85, 2.35, TimeDelta::zero())
+            .after(now)
+            .unwrap();
+
+        let before = sun(SolarEvent::Sunset, 48.85, 2.35, TimeDelta::minutes(-15));
+
+        assert_eq!(before.after(now), Some(sunset - TimeDelta::minutes(15)));
+
+        // Between the order and the sunset itself, the order is next due the day after.
+        let next = before.after(sunset - TimeDelta::minutes(5)).unwrap();
+
+        assert!(next > sunset + TimeDelta::hours(23));
+        assert!(next < sunset + TimeDelta::hours(25));
+    }
+
+    #[test]
+    fn sunrise_after_polar_night() {
+        // Tromsø, where the sun does not rise from late November to mid-January.
+        let sunrise = sun(SolarEvent::Sunrise, 69.65, 18.96, TimeDelta::zero())
+            .after(local(2024, 12, 1, 12, 0))
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(sunrise.date_naive().year(), 2025);
+        assert_eq!(sunrise.date_naive().month(), 1);
+    }
+
+    #[test]
+    fn sunrise_is_looked_for_up_to_a_year_ahead() {
+        // Near the pole, the sun rises once a year, in March.
+        let now = local(2024, 4, 1, 12, 0);
+
+        let sunrise = sun(SolarEvent::Sunrise, 89.9, 0.0, TimeDelta::zero())
+            .after(now)
+            .unwrap();
+
+        assert!(sunrise - now > TimeDelta::days(300));
+        assert!(sunrise - now < TimeDelta::days(SUN_LOOKAHEAD as i64));
+    }
+}
@@ -1,33 +1,70 @@
-use std::str::FromStr;
+use std::{path::Path, str::FromStr};
 
 use log::{info, debug, warn};
-use somfy_rts::{SomfyRTSDongle, Waiting, Ready, Alive, AddressVal, WireFormat, Response};
-use anyhow::{Result, bail, anyhow};
+use somfy_rts::{SomfyRTSDongle, Waiting, Ready, Alive, AddressVal, WireFormat, Response, transport};
+use anyhow::{Result, bail, anyhow, Context};
 
-pub async fn get_rts_dongle(serial: Option<String>) -> Result<SomfyRTSDongle<Waiting>> {
-    match serial.as_deref().map(SomfyRTSDongle::<Waiting>::from_str) {
-        None => {
-            info!(target: "get_rts_dongle", "No dongle was provided.");
-            let dongles = somfy_rts::detect();
+/// Serial ports of the dongles to drive: the ones given, or every dongle that can be found.
+pub fn dongle_ports(serial: &[String]) -> Result<Vec<String>> {
+    if !serial.is_empty() {
+        return Ok(serial.to_vec());
+    }
 
-            if dongles.is_empty() {
-                bail!("Found 0 dongles");
-            }
-            info!(
-                "Found {} dongles, using dongle at: {}",
-                dongles.len(),
-                &dongles[0].port_name
-            );
+    info!(target: "dongle_ports", "No dongle was provided.");
 
-            Ok(somfy_rts::new(&dongles[0].port_name)?)
-        }
+    let ports = somfy_rts::detect()
+        .into_iter()
+        .map(|dongle| dongle.port_name)
+        .collect::<Vec<_>>();
+
+    if ports.is_empty() {
+        bail!("Found 0 dongles");
+    }
+
+    info!(
+        "Found {} dongles, using dongles at: {}",
+        ports.len(),
+        ports.join(", ")
+    );
+
+    Ok(ports)
+}
+
+/// What the dongle tells about itself when it is brought up.
+pub struct DongleInfo {
+    pub port: String,
 
-        Some(dongle) => Ok(dongle?),
+    pub firmware: String,
+
+    pub alive: Alive,
+}
+
+impl DongleInfo {
+    pub fn serial(&self) -> &str {
+        &self.alive.id[0]
     }
 }
 
-pub async fn init_dongle(dongle: SomfyRTSDongle<Waiting>) -> Result<(SomfyRTSDongle<Ready>, Alive)> {
-    let (_, mut dongle_ready) = dongle.initialize(WireFormat::CryptoOff).await?;
+/// Opens the dongle at `port`, recording its traffic to a new capture in `record` if given,
+/// then brings it up.
+pub async fn init_dongle(
+    port: String,
+    record: Option<&Path>,
+) -> Result<(SomfyRTSDongle<Ready>, DongleInfo)> {
+    let mut dongle = SomfyRTSDongle::<Waiting>::from_str(&port)
+        .with_context(|| format!("Failed to open dongle at '{}'", port))?;
+
+    if let Some(dir) = record {
+        let capture = transport::capture_file(dir, &port);
+
+        dongle = dongle
+            .record(&capture)
+            .with_context(|| format!("Failed to record to '{}'", capture.display()))?;
+
+        info!(target: "init_dongle", "Recording traffic to '{}'.", capture.display());
+    }
+
+    let (firmware, mut dongle_ready) = dongle.initialize(WireFormat::CryptoOff).await?;
 
     let is_alive: Result<Alive, String> = dongle_ready.test_alive().await?.into();
 
@@ -36,7 +73,16 @@ pub async fn init_dongle(dongle: SomfyRTSDongle<Waiting>) -> Result<(SomfyRTSDon
          {:?}", is_alive);
     }
 
-    Ok((dongle_ready, is_alive.map_err(|e| anyhow!(e))?))
+    let alive = is_alive.map_err(|e| anyhow!(e))?;
+
+    Ok((
+        dongle_ready,
+        DongleInfo {
+            port,
+            firmware,
+            alive,
+        },
+    ))
 }
 
 pub async fn list_usable_blinds(dongle: &mut SomfyRTSDongle<Ready>) -> Vec<(u8, AddressVal)> {
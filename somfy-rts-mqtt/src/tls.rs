@@ -0,0 +1,317 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc, time::SystemTime};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, ClientConfig, PrivateKey, RootCertStore, ServerName,
+};
+use rustls_pemfile::Item;
+
+use crate::config::TlsConfig;
+
+/// Builds the TLS setup used to reach the broker over `mqtts` or `wss`.
+///
+/// The broker's certificate is checked against `ca_file` when given, and against the
+/// system's trust store otherwise, unless `insecure` is set.
+pub fn client_config(tls: &TlsConfig) -> Result<ClientConfig> {
+    client_config_with(tls, system_certs)
+}
+
+/// Builds the TLS setup, with `system_certs` loading the system's trusted certificates (DER).
+fn client_config_with(
+    tls: &TlsConfig,
+    system_certs: impl FnOnce() -> Result<Vec<Vec<u8>>>,
+) -> Result<ClientConfig> {
+    // Self-signed brokers often run where the system has no trust store to speak of.
+    let roots = if tls.insecure {
+        RootCertStore::empty()
+    } else {
+        trusted_roots(tls, system_certs)?
+    };
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+
+    let mut config = match (tls.client_cert.as_deref(), tls.client_key.as_deref()) {
+        (Some(cert), Some(key)) => {
+            let certs = read_certs(cert)?.into_iter().map(Certificate).collect();
+
+            builder
+                .with_single_cert(certs, read_key(key)?)
+                .context("Bad client certificate or key")?
+        }
+
+        (None, None) => builder.with_no_client_auth(),
+
+        _ => bail!("Client certificate and key go together: provide both or none"),
+    };
+
+    if tls.insecure {
+        warn!(target: "tls", "The MQTT broker's certificate will not be checked.");
+
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(AcceptAnyCertificate));
+    }
+
+    Ok(config)
+}
+
+fn trusted_roots(
+    tls: &TlsConfig,
+    system_certs: impl FnOnce() -> Result<Vec<Vec<u8>>>,
+) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+
+    match tls.ca_file.as_deref() {
+        Some(path) => {
+            let (_, ignored) = roots.add_parsable_certificates(&read_certs(path)?);
+
+            if ignored > 0 {
+                warn!(target: "tls", "Ignored {} invalid certificates in '{}'.", ignored, path.display());
+            }
+        }
+
+        None => {
+            roots.add_parsable_certificates(&system_certs()?);
+        }
+    }
+
+    if roots.is_empty() {
+        bail!("No trusted certificate to check the MQTT broker against");
+    }
+
+    Ok(roots)
+}
+
+fn system_certs() -> Result<Vec<Vec<u8>>> {
+    let certs = rustls_native_certs::load_native_certs()
+        .context("Failed to load the system's trusted certificates")?;
+
+    Ok(certs.into_iter().map(|cert| cert.0).collect())
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open certificate file '{}'", path.display()))?;
+
+    rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to read certificates from '{}'", path.display()))
+}
+
+/// Reads the first private key found in `path`, whatever its kind.
+fn read_key(path: &Path) -> Result<PrivateKey> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open key file '{}'", path.display()))?;
+
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to read key from '{}'", path.display()))?;
+
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or(anyhow!("No private key found in '{}'", path.display()))
+}
+
+/// Lets self-signed brokers through, at the cost of any protection against impersonation.
+struct AcceptAnyCertificate;
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use rumqttc::{AsyncClient, ConnectionError, Event, Packet};
+    use rustls::{server::AllowAnyAuthenticatedClient, ServerConfig};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        time,
+    };
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+    use crate::config::{Config, Transport};
+
+    fn fixture(name: &str) -> PathBuf {
+        [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", name]
+            .iter()
+            .collect()
+    }
+
+    /// Stands in for a broker reached over TLS, letting in any client through the handshake.
+    /// Its certificate, for `localhost`, is issued by the CA in `ca.pem`.
+    async fn broker(client_auth: bool) -> u16 {
+        let certs = read_certs(&fixture("broker.pem"))
+            .unwrap()
+            .into_iter()
+            .map(Certificate)
+            .collect();
+
+        let key = read_key(&fixture("broker-key.pem")).unwrap();
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+
+        let builder = if client_auth {
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(&read_certs(&fixture("ca.pem")).unwrap());
+
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let acceptor = TlsAcceptor::from(Arc::new(builder.with_single_cert(certs, key).unwrap()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let Ok(mut stream) = acceptor.accept(stream).await else {
+                    continue;
+                };
+
+                tokio::spawn(async move {
+                    let mut packet = [0u8; 256];
+
+                    // Whatever the CONNECT packet holds, CONNACK.
+                    if stream.read(&mut packet).await.unwrap_or(0) > 0 {
+                        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+                    }
+
+                    while stream.read(&mut packet).await.unwrap_or(0) > 0 {}
+                });
+            }
+        });
+
+        port
+    }
+
+    async fn connect(port: u16, tls: TlsConfig) -> Result<Event, ConnectionError> {
+        let mut config = Config::default();
+
+        config.mqtt.host = Some("localhost".to_string());
+        config.mqtt.port = port;
+        config.mqtt.transport = Transport::Mqtts;
+        config.mqtt.tls = tls;
+
+        let (_client, mut eventloop) = AsyncClient::new(config.mqtt_options().unwrap(), 10);
+
+        time::timeout(Duration::from_secs(5), eventloop.poll())
+            .await
+            .expect("The broker did not answer")
+    }
+
+    fn connected(event: Result<Event, ConnectionError>) -> bool {
+        matches!(event, Ok(Event::Incoming(Packet::ConnAck(_))))
+    }
+
+    #[test]
+    fn insecure_needs_no_trusted_certificate() {
+        let tls = TlsConfig {
+            insecure: true,
+            ..TlsConfig::default()
+        };
+
+        // The system's trust store is not even looked at.
+        assert!(client_config_with(&tls, || panic!("Loaded the system's certificates")).is_ok());
+    }
+
+    #[test]
+    fn empty_trust_store_is_refused() {
+        // As on the bare systems self-signed brokers often serve.
+        let error = client_config_with(&TlsConfig::default(), || Ok(Vec::new())).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "No trusted certificate to check the MQTT broker against"
+        );
+    }
+
+    #[test]
+    fn system_trust_store_is_used_without_ca_file() {
+        let system_certs = || read_certs(&fixture("ca.pem"));
+
+        assert!(client_config_with(&TlsConfig::default(), system_certs).is_ok());
+    }
+
+    #[test]
+    fn ca_file_replaces_system_trust_store() {
+        let tls = TlsConfig {
+            ca_file: Some(fixture("ca.pem")),
+            ..TlsConfig::default()
+        };
+
+        assert!(client_config_with(&tls, || panic!("Loaded the system's certificates")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn broker_is_checked_against_ca_file() {
+        let port = broker(false).await;
+
+        let tls = TlsConfig {
+            ca_file: Some(fixture("ca.pem")),
+            ..TlsConfig::default()
+        };
+
+        assert!(connected(connect(port, tls).await));
+    }
+
+    #[tokio::test]
+    async fn broker_from_another_ca_is_refused() {
+        let port = broker(false).await;
+
+        let tls = TlsConfig {
+            ca_file: Some(fixture("other-ca.pem")),
+            ..TlsConfig::default()
+        };
+
+        assert!(connect(port, tls).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn insecure_lets_unknown_broker_through() {
+        let port = broker(false).await;
+
+        let tls = TlsConfig {
+            ca_file: Some(fixture("other-ca.pem")),
+            insecure: true,
+            ..TlsConfig::default()
+        };
+
+        assert!(connected(connect(port, tls).await));
+    }
+
+    #[tokio::test]
+    async fn client_certificate_is_presented() {
+        let port = broker(true).await;
+
+        let tls = TlsConfig {
+            ca_file: Some(fixture("ca.pem")),
+            client_cert: Some(fixture("client.pem")),
+            client_key: Some(fixture("client-key.pem")),
+            insecure: false,
+        };
+
+        assert!(connected(connect(port, tls).await));
+    }
+}
@@ -0,0 +1,211 @@
+/// Every MQTT topic the bridge publishes to or listens on, derived from the configured prefixes.
+#[derive(Debug, Clone)]
+pub struct Topics {
+    base: String,
+    discovery: String,
+}
+
+/// What an incoming message is about, according to its topic.
+#[derive(Debug)]
+pub enum Route<'a> {
+    Reboot,
+    Pair,
+    Unpair(&'a str),
+    CoverSet(&'a str),
+    CoverTilt(&'a str),
+    GroupSet(&'a str),
+    ScheduleSet(&'a str),
+    SceneSet(&'a str),
+    DiscoveryConfig {
+        component: &'a str,
+        node_id: &'a str,
+        object_id: &'a str,
+    },
+}
+
+impl Topics {
+    pub fn new(base: &str, discovery: &str) -> Self {
+        Topics {
+            base: base.to_string(),
+            discovery: discovery.to_string(),
+        }
+    }
+
+    /// Topics about the dongle itself, such as `<base>/dongle/state`.
+    pub fn dongle(&self, leaf: &str) -> String {
+        format!("{}/dongle/{}", self.base, leaf)
+    }
+
+    /// Topics about a blind, such as `<base>/cover/<id>/set`.
+    pub fn cover(&self, id: u8, leaf: &str) -> String {
+        format!("{}/cover/{}/{}", self.base, id, leaf)
+    }
+
+    /// Topics about a group of blinds, such as `<base>/group/<name>/set`.
+    pub fn group(&self, name: &str, leaf: &str) -> String {
+        format!("{}/group/{}/{}", self.base, name, leaf)
+    }
+
+    /// Topics about a schedule, such as `<base>/schedule/<name>/set`.
+    pub fn schedule(&self, name: &str, leaf: &str) -> String {
+        format!("{}/schedule/{}/{}", self.base, name, leaf)
+    }
+
+    /// Topics about a scene, such as `<base>/scene/<name>/set`.
+    pub fn scene(&self, name: &str, leaf: &str) -> String {
+        format!("{}/scene/{}/{}", self.base, name, leaf)
+    }
+
+    pub fn discovery_config(&self, component: &str, node_id: &str, object_id: &str) -> String {
+        format!(
+            "{}/{}/{}/{}/config",
+            self.discovery, component, node_id, object_id
+        )
+    }
+
+    /// Filter matching the configs of every `component` announced under `node_id`.
+    pub fn discovery_configs(&self, component: &str, node_id: &str) -> String {
+        self.discovery_config(component, node_id, "+")
+    }
+
+    pub fn route<'a>(&self, topic: &'a str) -> Option<Route<'a>> {
+        if let Some(rest) = strip_level(topic, &self.discovery) {
+            if let [component, node_id, object_id, "config"] = levels(rest).as_slice() {
+                return Some(Route::DiscoveryConfig {
+                    component,
+                    node_id,
+                    object_id,
+                });
+            }
+        }
+
+        let route = match levels(strip_level(topic, &self.base)?).as_slice() {
+            ["dongle", "reboot"] => Route::Reboot,
+            ["dongle", "pair"] => Route::Pair,
+            ["dongle", "unpair", id] => Route::Unpair(id),
+            ["cover", id, "set"] => Route::CoverSet(id),
+            ["cover", id, "tilt"] => Route::CoverTilt(id),
+            ["group", name, "set"] => Route::GroupSet(name),
+            ["schedule", name, "set"] => Route::ScheduleSet(name),
+            ["scene", name, "set"] => Route::SceneSet(name),
+            _ => return None,
+        };
+
+        Some(route)
+    }
+}
+
+/// Strips `prefix` from `topic`, provided it covers whole levels.
+fn strip_level<'a>(topic: &'a str, prefix: &str) -> Option<&'a str> {
+    topic.strip_prefix(prefix)?.strip_prefix('/')
+}
+
+fn levels(topic: &str) -> Vec<&str> {
+    topic.split('/').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topics() -> Topics {
+        Topics::new("somfy-rts/upstairs", "homeassistant")
+    }
+
+    #[test]
+    fn nested_prefix_is_routed() {
+        let topics = topics();
+
+        assert!(matches!(
+            topics.route("somfy-rts/upstairs/cover/12/set"),
+            Some(Route::CoverSet("12"))
+        ));
+        assert!(matches!(
+            topics.route("somfy-rts/upstairs/cover/12/tilt"),
+            Some(Route::CoverTilt("12"))
+        ));
+        assert!(matches!(
+            topics.route("somfy-rts/upstairs/dongle/pair"),
+            Some(Route::Pair)
+        ));
+        assert!(matches!(
+            topics.route("somfy-rts/upstairs/dongle/unpair/7"),
+            Some(Route::Unpair("7"))
+        ));
+        assert!(matches!(
+            topics.route("somfy-rts/upstairs/dongle/reboot"),
+            Some(Route::Reboot)
+        ));
+    }
+
+    #[test]
+    fn groups_schedules_and_scenes_are_routed() {
+        let topics = topics();
+
+        assert!(matches!(
+            topics.route("somfy-rts/upstairs/group/bedrooms/set"),
+            Some(Route::GroupSet("bedrooms"))
+        ));
+        assert!(matches!(
+            topics.route("somfy-rts/upstairs/schedule/morning/set"),
+            Some(Route::ScheduleSet("morning"))
+        ));
+        assert!(matches!(
+            topics.route("somfy-rts/upstairs/scene/movie/set"),
+            Some(Route::SceneSet("movie"))
+        ));
+    }
+
+    #[test]
+    fn discovery_configs_are_routed() {
+        assert!(matches!(
+            topics().route("homeassistant/cover/somfy-rts-0123/somfy-rts-0A0B0C/config"),
+            Some(Route::DiscoveryConfig {
+                component: "cover",
+                node_id: "somfy-rts-0123",
+                object_id: "somfy-rts-0A0B0C",
+            })
+        ));
+    }
+
+    #[test]
+    fn topics_outside_prefix_are_rejected() {
+        let topics = topics();
+
+        for topic in [
+            // Another dongle, or the top of the nested prefix.
+            "somfy-rts/downstairs/cover/12/set",
+            "somfy-rts/cover/12/set",
+            // The prefix must cover whole levels.
+            "somfy-rts/upstairs2/cover/12/set",
+            "somfy-rts/upstairscover/12/set",
+            // Known leaves, at the wrong depth.
+            "somfy-rts/upstairs/cover/12/set/extra",
+            "somfy-rts/upstairs/cover/set",
+            "somfy-rts/upstairs/dongle/unpair",
+            "homeassistant/cover/somfy-rts-0123/config",
+            "somfy-rts/upstairs",
+            "",
+        ] {
+            assert!(topics.route(topic).is_none(), "{topic}");
+        }
+    }
+
+    #[test]
+    fn published_topics_are_routed_back() {
+        let topics = topics();
+
+        assert!(matches!(
+            topics.route(&topics.cover(12, "set")),
+            Some(Route::CoverSet("12"))
+        ));
+        assert!(matches!(
+            topics.route(&topics.dongle("pair")),
+            Some(Route::Pair)
+        ));
+        assert!(matches!(
+            topics.route(&topics.scene("movie", "set")),
+            Some(Route::SceneSet("movie"))
+        ));
+    }
+}
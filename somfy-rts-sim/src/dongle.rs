@@ -0,0 +1,219 @@
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use somfy_rts::frame::command_name;
+
+/// Address of the first slot; slots come with consecutive addresses, as on the real stick.
+const BASE_ADDRESS: u32 = 0x1A0000;
+
+/// Lines the dongle prints in factory mode.
+const FACTORY_LINES: usize = 11;
+
+/// What a slot holds. Slots that were never used hold their address and a rolling code of 0.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Slot {
+    pub address: String,
+    pub rolling_code: u16,
+}
+
+/// How the simulated dongle behaves.
+pub struct Options {
+    pub serial: String,
+    pub firmware: String,
+    pub rssi: i32,
+
+    /// Every nth command is refused.
+    pub ko_every: Option<u32>,
+
+    /// Commands always refused, by name.
+    pub ko_on: Vec<String>,
+
+    /// File the slots are kept in across runs.
+    pub slots: Option<PathBuf>,
+}
+
+pub struct Dongle {
+    options: Options,
+    slots: BTreeMap<u8, Slot>,
+    commands: u32,
+}
+
+fn ok(fields: Value) -> Value {
+    let mut answer = Map::new();
+
+    answer.insert("ACK".to_string(), json!("DONGLE_OK"));
+
+    if let Value::Object(fields) = fields {
+        answer.extend(fields);
+    }
+
+    Value::Object(answer)
+}
+
+fn refused(error: &str) -> Value {
+    json!({ "ACK": "DONGLE_KO", "ERROR": error })
+}
+
+fn slot_id(id: &Value) -> Option<u8> {
+    id.as_u64()
+        .and_then(|id| u8::try_from(id).ok())
+        .filter(|id| (1..=100).contains(id))
+}
+
+impl Slot {
+    fn new(id: u8) -> Self {
+        Slot {
+            address: format!("{:06X}", BASE_ADDRESS + id as u32),
+            rolling_code: 0,
+        }
+    }
+
+    fn address_val(&self, id: u8) -> Value {
+        json!({ "ADDRESS-VAL": [id, self.address, format!("{:04X}", self.rolling_code)] })
+    }
+}
+
+impl Dongle {
+    /// Brings the dongle up, with the slots kept from a previous run, if any.
+    pub fn open(options: Options) -> io::Result<Self> {
+        let slots = match options.slots.as_ref().map(fs::read_to_string) {
+            Some(Ok(content)) => serde_json::from_str(&content)?,
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => BTreeMap::new(),
+        };
+
+        Ok(Dongle {
+            options,
+            slots,
+            commands: 0,
+        })
+    }
+
+    /// Answer to `$HELLOSOMFYBG3174` or `$CRYPTO_OFF_3145`.
+    pub fn init(&self) -> String {
+        format!("RTSDONGLE,OK,{}\r\n", self.options.firmware)
+    }
+
+    /// Answer to `$GOTO-FACTORY`. What the real stick prints is not known yet.
+    pub fn factory(&self) -> String {
+        (0..FACTORY_LINES)
+            .map(|line| match line {
+                0 => format!("SERIAL,{}\r\n", self.options.serial),
+                1 => format!("FIRMWARE,{}\r\n", self.options.firmware),
+                _ => format!("FACTORY,{}\r\n", line),
+            })
+            .collect()
+    }
+
+    pub fn answer(&mut self, command: &Value) -> Value {
+        self.commands += 1;
+
+        let name = command_name(command);
+
+        if self
+            .options
+            .ko_every
+            .is_some_and(|every| self.commands.is_multiple_of(every))
+            || self
+                .options
+                .ko_on
+                .iter()
+                .any(|ko| ko.eq_ignore_ascii_case(&name))
+        {
+            return refused("INJECTED");
+        }
+
+        let Some((kind, args)) = command
+            .as_object()
+            .and_then(|command| command.iter().next())
+        else {
+            return refused("UNKNOWN_COMMAND");
+        };
+
+        match (kind.as_str(), args) {
+            ("CMD-DONGLE", Value::String(command)) => match command.as_str() {
+                "ALIVE" => ok(json!({
+                    "RSSI-VAL": self.options.rssi,
+                    "ID": [self.options.serial, self.options.firmware, "SOMFY-RTS-SIM"],
+                })),
+
+                "FACTORY-RESET" => {
+                    self.slots.clear();
+                    self.save();
+                    ok(json!({}))
+                }
+
+                "RESETHW" | "BCHECK" | "BSTART" => ok(json!({})),
+
+                _ => refused("UNKNOWN_COMMAND"),
+            },
+
+            ("CMD-RTS", Value::Array(args)) => {
+                let order = args.first().and_then(Value::as_str);
+
+                let Some(id) = args.get(1).and_then(slot_id) else {
+                    return refused("BAD_ID");
+                };
+
+                match order {
+                    Some("UP" | "DOWN" | "STOP" | "MY" | "PROG" | "PROG_RT" | "4_CYCLES") => {}
+                    _ => return refused("UNKNOWN_COMMAND"),
+                }
+
+                let slot = self.slots.entry(id).or_insert_with(|| Slot::new(id));
+
+                slot.rolling_code = slot.rolling_code.wrapping_add(1);
+
+                let answer = ok(slot.address_val(id));
+
+                self.save();
+
+                answer
+            }
+
+            ("GET-ADDRESS", id) => match slot_id(id) {
+                Some(id) => ok(self
+                    .slots
+                    .get(&id)
+                    .cloned()
+                    .unwrap_or_else(|| Slot::new(id))
+                    .address_val(id)),
+
+                None => refused("BAD_ID"),
+            },
+
+            ("RESET-ADDRESS", id) => match slot_id(id) {
+                Some(id) => {
+                    self.slots.remove(&id);
+                    self.save();
+                    ok(json!({}))
+                }
+
+                None => refused("BAD_ID"),
+            },
+
+            ("LED", Value::Array(_)) => ok(json!({})),
+
+            _ => refused("UNKNOWN_COMMAND"),
+        }
+    }
+
+    /// Writes the slots down, if they are to be kept.
+    fn save(&self) {
+        let Some(path) = self.options.slots.as_ref() else {
+            return;
+        };
+
+        let temporary = path.with_extension("tmp");
+
+        let saved = serde_json::to_string_pretty(&self.slots)
+            .map_err(io::Error::from)
+            .and_then(|content| fs::write(&temporary, content))
+            .and_then(|_| fs::rename(&temporary, path));
+
+        if let Err(e) = saved {
+            eprintln!("Failed to save slots to {}: {}", path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+#[cfg(unix)]
+mod dongle;
+#[cfg(unix)]
+mod pty;
+
+#[cfg(unix)]
+fn main() {
+    pty::run();
+}
+
+#[cfg(not(unix))]
+fn main() {
+    println!("The simulator serves the dongle on a pseudo-terminal, which only Unix systems have.");
+    std::process::exit(-1);
+}
@@ -0,0 +1,191 @@
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::{fs::symlink, io::RawFd},
+    path::PathBuf,
+    process::exit,
+    thread,
+    time::Duration,
+};
+
+use clap::Parser;
+use nix::{
+    errno::Errno,
+    pty::openpty,
+    sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg},
+    unistd::{read, ttyname, write},
+};
+
+use somfy_rts::frame::{Decoder, Frame};
+
+use crate::dongle::{Dongle, Options};
+
+const INIT_MESSAGES: [&str; 2] = ["$HELLOSOMFYBG3174", "$CRYPTO_OFF_3145"];
+const FACTORY_MESSAGE: &str = "$GOTO-FACTORY";
+
+/// Behaves like the dongle, on a pseudo-terminal whose path is printed on startup.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Serial number the dongle reports
+    #[arg(long, default_value = "SIM0000001")]
+    serial: String,
+
+    /// Firmware version the dongle reports
+    #[arg(long, default_value = "1.0.0")]
+    firmware: String,
+
+    /// Signal strength the dongle reports
+    #[arg(long, default_value_t = -60, allow_hyphen_values = true)]
+    rssi: i32,
+
+    /// Time taken to answer each message, such as 80ms
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "50ms")]
+    latency: Duration,
+
+    /// Refuse every Nth command with DONGLE_KO
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    ko_every: Option<u32>,
+
+    /// Always refuse this command (UP, GET-ADDRESS, ALIVE...) with DONGLE_KO, repeated for
+    /// each command
+    #[arg(long, value_name = "COMMAND")]
+    ko_on: Vec<String>,
+
+    /// File to keep the slots in across runs. They are forgotten on exit otherwise.
+    #[arg(long, value_name = "FILE")]
+    slots: Option<PathBuf>,
+
+    /// Also make the pseudo-terminal reachable at this path, through a symbolic link
+    #[arg(long, value_name = "PATH")]
+    link: Option<PathBuf>,
+}
+
+/// Opens the pseudo-terminal, in raw mode so that nothing gets in the way of the bytes.
+/// Returns its master side, and the path of the side given to clients.
+fn open_pty() -> nix::Result<(RawFd, PathBuf)> {
+    let pty = openpty(None, None)?;
+
+    let mut termios = tcgetattr(pty.slave)?;
+    cfmakeraw(&mut termios);
+    tcsetattr(pty.slave, SetArg::TCSANOW, &termios)?;
+
+    // The client side is kept open, so that reading does not fail while no client is.
+    Ok((pty.master, ttyname(pty.slave)?))
+}
+
+fn send(master: RawFd, latency: Duration, answer: &str) {
+    thread::sleep(latency);
+
+    eprintln!("-> {}", answer.trim_end());
+
+    let mut bytes = answer.as_bytes();
+
+    while !bytes.is_empty() {
+        match write(master, bytes) {
+            Ok(written) => bytes = &bytes[written..],
+            Err(Errno::EINTR | Errno::EAGAIN) => {}
+            Err(e) => {
+                eprintln!("Failed to write to the pseudo-terminal: {}", e);
+                exit(-1);
+            }
+        }
+    }
+}
+
+/// Serves the dongle on a pseudo-terminal, until killed.
+pub fn run() {
+    let args = Cli::parse();
+
+    let mut dongle = Dongle::open(Options {
+        serial: args.serial,
+        firmware: args.firmware,
+        rssi: args.rssi,
+        ko_every: args.ko_every,
+        ko_on: args.ko_on,
+        slots: args.slots.clone(),
+    })
+    .unwrap_or_else(|e| {
+        println!("Failed to read slots: {}", e);
+        exit(-1);
+    });
+
+    let (master, path) = open_pty().unwrap_or_else(|e| {
+        println!("Failed to open a pseudo-terminal: {}", e);
+        exit(-1);
+    });
+
+    if let Some(link) = args.link.as_ref() {
+        let _ = fs::remove_file(link);
+
+        if let Err(e) = symlink(&path, link) {
+            println!(
+                "Failed to link {} to {}: {}",
+                link.display(),
+                path.display(),
+                e
+            );
+            exit(-1);
+        }
+    }
+
+    println!("{}", path.display());
+    let _ = io::stdout().flush();
+
+    let mut decoder = Decoder::default();
+    let mut buffer = [0u8; 1024];
+
+    loop {
+        let n = match read(master, &mut buffer) {
+            Ok(n) => n,
+            Err(Errno::EINTR | Errno::EAGAIN) => continue,
+            Err(e) => {
+                eprintln!("Failed to read from the pseudo-terminal: {}", e);
+                exit(-1);
+            }
+        };
+
+        decoder.push(&buffer[..n]);
+
+        while let Some(frame) = decoder.next_frame() {
+            match frame {
+                Frame::Json(command) => {
+                    eprintln!("<- {}", command);
+
+                    let answer = dongle.answer(&command);
+
+                    send(master, args.latency, &answer.to_string());
+                }
+
+                Frame::Line(line) => eprintln!("<- {:?}, ignored", line),
+
+                Frame::Raw(bytes) => eprintln!("<- {:?}, ignored", String::from_utf8_lossy(&bytes)),
+            }
+        }
+
+        // Messages starting with `$` are held back by the decoder, having no terminator.
+        let Some(Frame::Raw(bytes)) = decoder.flush() else {
+            continue;
+        };
+
+        let message = String::from_utf8_lossy(&bytes);
+
+        if INIT_MESSAGES.contains(&message.as_ref()) {
+            eprintln!("<- {}", message);
+            send(master, args.latency, &dongle.init());
+        } else if message == FACTORY_MESSAGE {
+            eprintln!("<- {}", message);
+            send(master, args.latency, &dongle.factory());
+        } else if !message.starts_with('$')
+            || INIT_MESSAGES
+                .iter()
+                .chain([&FACTORY_MESSAGE])
+                .any(|known| known.starts_with(message.as_ref()))
+        {
+            // Only part of it came through yet.
+            decoder.push(&bytes);
+        } else {
+            eprintln!("<- {:?}, ignored", message);
+        }
+    }
+}
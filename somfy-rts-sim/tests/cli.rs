@@ -0,0 +1,85 @@
+#![cfg(unix)]
+
+use std::{
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+};
+
+use serde_json::Value;
+
+/// Stops the simulator, however the test ends.
+struct Sim(Child);
+
+impl Drop for Sim {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Starts the simulator, returning it along with the path of its pseudo-terminal.
+fn sim() -> (Sim, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_somfy-rts-sim"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut path = String::new();
+
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut path)
+        .unwrap();
+
+    (Sim(child), path.trim_end().to_string())
+}
+
+/// Builds `somfy-rts-cli`, which belongs to another package of the workspace.
+fn cli() -> PathBuf {
+    let build = Command::new(env!("CARGO"))
+        .args([
+            "build",
+            "--quiet",
+            "--package",
+            "somfy-rts-cli",
+            "--message-format",
+            "json",
+        ])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+
+    assert!(build.status.success(), "Failed to build somfy-rts-cli");
+
+    String::from_utf8_lossy(&build.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .find(|message| message["target"]["name"] == "somfy-rts-cli")
+        .and_then(|message| message["executable"].as_str().map(PathBuf::from))
+        .expect("No somfy-rts-cli executable was built")
+}
+
+#[test]
+fn cli_reads_a_slot() {
+    let (_sim, path) = sim();
+
+    let output = Command::new(cli())
+        .args(["-s", &path, "get-address", "1..=1"])
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("Dongle id:SIM0000001, SOMFY-RTS-SIM 1.0.0"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains(r#"[Number(1), String("1A0001"), String("0000")]"#),
+        "{}",
+        stdout
+    );
+}
@@ -1,22 +1,26 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, io, path::Path, sync::Arc, time::Instant};
 
 use bytes::BytesMut;
 use log::trace;
 use serde::de::DeserializeOwned;
 use tokio::io::AsyncWriteExt;
-use tokio_serial::SerialStream;
+
+pub use self::error::Error;
 
 use self::{
-    error::Error,
     model::Command,
     read::{JsonResponse, LinesResponse, Response},
 };
 
-use super::{AddressVal, Alive, Empty, LedAction, LedColor, RtsCommand};
+use super::{
+    scene::{Action, SceneError},
+    transport::{Recorder, Transport},
+    AddressVal, Alive, Empty, Exchange, LedAction, LedColor, RtsCommand,
+};
 
 mod error;
 pub mod model;
-mod read;
+pub(crate) mod read;
 pub mod ser;
 
 pub trait State {}
@@ -29,20 +33,29 @@ impl State for Waiting {}
 impl State for Factory {}
 impl State for Ready {}
 
+/// Told about every command sent to the dongle, once it is answered (or failed).
+pub type Observer = Arc<dyn Fn(&Exchange) + Send + Sync>;
+
 pub struct SomfyRTSDongle<S> {
     state: S,
-    device: SerialStream,
+    device: Box<dyn Transport>,
+    observer: Option<Observer>,
 }
 
-pub fn somfy_dongle(device: SerialStream) -> SomfyRTSDongle<Waiting> {
+pub fn somfy_dongle(device: impl Transport + 'static) -> SomfyRTSDongle<Waiting> {
     SomfyRTSDongle {
         state: Waiting,
-        device,
+        device: Box::new(device),
+        observer: None,
     }
 }
 
 impl<S: State> SomfyRTSDongle<S> {
-    async fn send_raw<T>(&mut self, cmd: &str, response: Box<dyn Response<T>>) -> Result<T, Error> {
+    pub fn observe(&mut self, observer: Observer) {
+        self.observer = Some(observer);
+    }
+
+    async fn send_raw<T>(&mut self, cmd: &str, response: Box<dyn Response<T> + Send>) -> Result<T, Error> {
         let mut buffer = BytesMut::with_capacity(1024);
 
         self.device.write_all(cmd.as_bytes()).await?;
@@ -58,6 +71,14 @@ impl<S: State> SomfyRTSDongle<S> {
 }
 
 impl SomfyRTSDongle<Waiting> {
+    /// Records all traffic with the dongle to capture file `path`, from now on.
+    pub fn record(self, path: &Path) -> io::Result<Self> {
+        Ok(SomfyRTSDongle {
+            device: Box::new(Recorder::new(self.device, path)?),
+            ..self
+        })
+    }
+
     pub async fn initialize(
         mut self,
         format: WireFormat,
@@ -72,6 +93,7 @@ impl SomfyRTSDongle<Waiting> {
                 SomfyRTSDongle {
                     state: Ready(format),
                     device: self.device,
+                    observer: self.observer,
                 },
             )
         })
@@ -104,6 +126,7 @@ impl SomfyRTSDongle<Waiting> {
             .map(|info| SomfyRTSDongle {
                 state: Factory(info),
                 device: self.device,
+                observer: self.observer,
             })
     }
 }
@@ -121,6 +144,8 @@ impl SomfyRTSDongle<Ready> {
         &mut self,
         cmd: Command,
     ) -> Result<model::Response<T>, Error> {
+        let blind = cmd.blind();
+
         let cmd = serde_json::to_string(&cmd).unwrap();
 
         trace!(target:"libsomfy_rts::send_command", "Sending: {}", cmd);
@@ -129,7 +154,20 @@ impl SomfyRTSDongle<Ready> {
 
         let response = Box::new(JsonResponse);
 
-        let value = self.send_raw(&encoded, response).await?;
+        let started = Instant::now();
+
+        let value = self.send_raw(&encoded, response).await;
+
+        if let Some(observer) = self.observer.as_ref() {
+            observer(&Exchange {
+                command: serde_json::from_str(&cmd).unwrap_or_default(),
+                blind,
+                answer: value.as_ref().map(Clone::clone).map_err(ToString::to_string),
+                latency: started.elapsed(),
+            });
+        }
+
+        let value = value?;
 
         trace!(target:"libsomfy_rts::send_command", "Raw cmd: {}", cmd);
 
@@ -209,6 +247,23 @@ impl SomfyRTSDongle<Ready> {
     ) -> Result<super::Response<AddressVal>, Error> {
         self.send_command(Command::CmdRts(cmd)).await
     }
+
+    /// Runs the actions of a scene in order, stopping at the first command the dongle refuses.
+    pub async fn run_scene(&mut self, actions: &[Action]) -> Result<(), SceneError> {
+        for action in actions {
+            match action {
+                Action::Send(cmd) => {
+                    if let super::Response::Err(e) = self.operate_blind(*cmd).await? {
+                        return Err(SceneError::Refused(*cmd, e));
+                    }
+                }
+
+                Action::Wait(duration) => tokio::time::sleep(*duration).await,
+            }
+        }
+
+        Ok(())
+    }
 }
 
 pub enum WireFormat {
@@ -239,10 +294,10 @@ impl WireFormat {
     }
 }
 
-fn encrypt(data: &str) -> String {
+fn encrypt(_data: &str) -> String {
     todo!()
 }
 
-fn decrypt(encrypted: &str) -> String {
+fn decrypt(_encrypted: &str) -> String {
     todo!()
 }
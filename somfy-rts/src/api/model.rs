@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use hex::FromHex;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -14,6 +16,43 @@ pub enum Command {
     ResetAddress(u8),
 }
 
+/// A command sent to the dongle, along with what it answered.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    /// The command, as sent to the dongle (before encoding).
+    pub command: Value,
+
+    /// Slot the command is about, if any.
+    pub blind: Option<u8>,
+
+    /// What the dongle answered, or why no answer could be read.
+    pub answer: Result<Value, String>,
+
+    /// Time taken by the dongle to answer.
+    pub latency: Duration,
+}
+
+impl Command {
+    /// The slot this command is about, if any.
+    pub fn blind(&self) -> Option<u8> {
+        match self {
+            Self::CmdRts(
+                RtsCommand::Up(id)
+                | RtsCommand::Down(id)
+                | RtsCommand::Prog(id)
+                | RtsCommand::My(id)
+                | RtsCommand::Stop(id)
+                | RtsCommand::ProgRt(id)
+                | RtsCommand::FourCycles(id),
+            )
+            | Self::GetAddress(id)
+            | Self::ResetAddress(id) => Some(*id),
+
+            Self::CmdDongle(_) | Self::SetAddress | Self::Led(..) => None,
+        }
+    }
+}
+
 #[allow(unused)]
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "SCREAMING-KEBAB-CASE")]
@@ -26,7 +65,7 @@ pub enum DongleCommand {
 }
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum RtsCommand {
     Up(u8),
     Down(u8),
@@ -38,7 +77,7 @@ pub enum RtsCommand {
 }
 
 #[allow(unused)]
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, Serialize)]
 #[serde(rename_all = "SCREAMING-KEBAB-CASE")]
 pub enum LedColor {
     Red,
@@ -46,7 +85,7 @@ pub enum LedColor {
 }
 
 #[allow(unused)]
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, Serialize)]
 #[serde(rename_all = "SCREAMING-KEBAB-CASE")]
 pub enum LedAction {
     Fix,
@@ -60,22 +99,23 @@ pub enum Response<T> {
     Err(String),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Empty {}
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "SCREAMING-KEBAB-CASE")]
 pub struct Alive {
     pub rssi_val: i32,
     pub id: [String; 3],
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING-KEBAB-CASE")]
 pub struct AddressVal {
     pub address_val: [Value; 3],
 }
 
+#[allow(unused)]
 pub struct BlindAddress {
     id: u8,
     addr: [u8; 3],
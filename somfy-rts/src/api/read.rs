@@ -2,10 +2,9 @@ use std::io::Cursor;
 
 use serde_json::{error::Category, Value};
 use thiserror::Error;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 use bytes::{Buf, BytesMut};
-use tokio_serial::SerialStream;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -15,6 +14,9 @@ pub enum Error {
     #[error("Failed to decode serial data as text.")]
     BadEncoding,
 
+    #[error("Failed to decode serial data as JSON: {}", .0)]
+    BadJson(serde_json::Error),
+
     #[error("Expected to read more bytes but reached EOS.")]
     EndOfStream,
 
@@ -26,27 +28,31 @@ fn get_byte(cursor: &mut Cursor<&[u8]>) -> Option<u8> {
     cursor.has_remaining().then(|| cursor.get_u8())
 }
 
-pub async fn try_read_stuff<'c, T>(
-    reader: &'c mut SerialStream,
+pub async fn try_read_stuff<'c, T, R: AsyncRead + Unpin>(
+    reader: &'c mut R,
     buffer: &'c mut BytesMut,
-    mut response: Box<dyn Response<T>>,
+    mut response: Box<dyn Response<T> + Send>,
 ) -> Result<T, Error> {
     loop {
         let mut cursor = Cursor::new(&buffer[..]);
 
-        if let Ok(res) = response.read(&mut cursor) {
-            let consumed = cursor.position() as usize;
-            buffer.advance(consumed);
+        match response.read(&mut cursor) {
+            Ok(res) => {
+                let consumed = cursor.position() as usize;
+                buffer.advance(consumed);
+
+                return Ok(res);
+            }
 
-            return Ok(res);
+            Err(Error::Incomplete) => {}
+
+            Err(e) => return Err(e),
         }
 
-        if reader.readable().await.is_ok() {
-            let new_bytes = reader.read_buf(buffer).await.map_err(Error::Io)?;
+        let new_bytes = reader.read_buf(buffer).await.map_err(Error::Io)?;
 
-            if new_bytes == 0 {
-                return Err(Error::EndOfStream);
-            }
+        if new_bytes == 0 {
+            return Err(Error::EndOfStream);
         }
     }
 }
@@ -108,16 +114,101 @@ impl JsonResponse {}
 impl Response<Value> for JsonResponse {
     fn read(&mut self, cursor: &mut Cursor<&[u8]>) -> Result<Value, Error> {
         let start = cursor.position() as usize;
-        let len = cursor.get_ref().len();
 
-        let available = &cursor.get_ref()[start..start + len];
+        let available = &cursor.get_ref()[start..];
 
-        let string = std::str::from_utf8(available).unwrap();
+        let mut values = serde_json::Deserializer::from_slice(available).into_iter::<Value>();
 
-        match serde_json::from_str::<Value>(string) {
-            Ok(v) => Ok(v),
-            Err(e) if e.classify() == Category::Eof => Err(Error::Incomplete),
-            _ => unreachable!(),
+        match values.next() {
+            Some(Ok(v)) => {
+                cursor.set_position((start + values.byte_offset()) as u64);
+                Ok(v)
+            }
+            Some(Err(e)) if e.classify() == Category::Eof => Err(Error::Incomplete),
+            Some(Err(e)) => Err(Error::BadJson(e)),
+            None => Err(Error::Incomplete),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    fn read_json(bytes: &[u8]) -> (Result<Value, Error>, u64) {
+        let mut cursor = Cursor::new(bytes);
+
+        (JsonResponse.read(&mut cursor), cursor.position())
+    }
+
+    #[test]
+    fn json_split_across_reads_is_incomplete() {
+        let (value, _) = read_json(br#"{"ACK":"DONGLE_"#);
+
+        assert!(matches!(value, Err(Error::Incomplete)));
+    }
+
+    #[test]
+    fn json_stops_after_first_value() {
+        let first = br#"{"ACK":"DONGLE_OK"}"#;
+        let bytes = [&first[..], br#"{"ACK":"DONGLE_KO"}"#].concat();
+
+        let (value, position) = read_json(&bytes);
+
+        assert_eq!(value.unwrap(), json!({ "ACK": "DONGLE_OK" }));
+        assert_eq!(position, first.len() as u64);
+    }
+
+    #[test]
+    fn json_garbage_is_an_error() {
+        let (value, position) = read_json(b"{\"ACK\":]");
+
+        assert!(matches!(value, Err(Error::BadJson(_))));
+        assert_eq!(position, 0);
+    }
+
+    #[tokio::test]
+    async fn read_waits_for_the_rest_of_a_value() {
+        let mut reader = AsyncReadExt::chain(&br#"{"ACK":"#[..], &br#""DONGLE_OK"}"#[..]);
+        let mut buffer = BytesMut::new();
+
+        let value = try_read_stuff(&mut reader, &mut buffer, Box::new(JsonResponse)).await;
+
+        assert_eq!(value.unwrap(), json!({ "ACK": "DONGLE_OK" }));
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_keeps_what_follows_a_value() {
+        let mut reader = &br#"{"ACK":"DONGLE_OK"}{"ACK":"DONGLE_KO"}"#[..];
+        let mut buffer = BytesMut::new();
+
+        let value = try_read_stuff(&mut reader, &mut buffer, Box::new(JsonResponse)).await;
+
+        assert_eq!(value.unwrap(), json!({ "ACK": "DONGLE_OK" }));
+        assert_eq!(&buffer[..], br#"{"ACK":"DONGLE_KO"}"#);
+    }
+
+    #[tokio::test]
+    async fn read_fails_on_garbage() {
+        let mut reader = &b"{]\r\n"[..];
+        let mut buffer = BytesMut::new();
+
+        let value = try_read_stuff(&mut reader, &mut buffer, Box::new(JsonResponse)).await;
+
+        assert!(matches!(value, Err(Error::BadJson(_))));
+    }
+
+    #[tokio::test]
+    async fn read_lines_across_reads() {
+        let mut reader = AsyncReadExt::chain(&b"RTSDONGLE,O"[..], &b"K,1.2.3\r\n"[..]);
+        let mut buffer = BytesMut::new();
+
+        let lines = try_read_stuff(&mut reader, &mut buffer, LinesResponse::new(1)).await;
+
+        assert_eq!(lines.unwrap(), ["RTSDONGLE,OK,1.2.3"]);
+    }
+}
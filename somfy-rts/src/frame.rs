@@ -0,0 +1,161 @@
+//! Splits the traffic seen on either side of the dongle into frames, for tools watching it
+//! go by rather than taking part in it.
+
+use std::io::Cursor;
+
+use bytes::{Buf, BytesMut};
+use serde_json::Value;
+
+use super::api::read::{Error, JsonResponse, LinesResponse, Response};
+
+#[derive(Debug, Clone)]
+pub enum Frame {
+    /// A line ended by CRLF, such as the dongle's answer to the init message.
+    Line(String),
+
+    Json(Value),
+
+    /// Bytes neither parser made sense of, such as messages starting with `$`, which have no
+    /// terminator.
+    Raw(Vec<u8>),
+}
+
+/// Name of a command as sent to the dongle: the order for RTS commands (`UP`), the command
+/// for dongle ones (`ALIVE`), its kind otherwise (`GET-ADDRESS`).
+pub fn command_name(command: &Value) -> String {
+    match command
+        .as_object()
+        .and_then(|command| command.iter().next())
+    {
+        Some((name, Value::Array(args))) if name == "CMD-RTS" => {
+            args.first().and_then(Value::as_str).unwrap_or(name)
+        }
+        Some((_, Value::String(command))) => command,
+        Some((name, _)) => name,
+        None => "UNKNOWN",
+    }
+    .to_string()
+}
+
+/// Gathers the bytes going one way, handing out frames as soon as they are complete.
+#[derive(Default)]
+pub struct Decoder {
+    buffer: BytesMut,
+}
+
+impl Decoder {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Next complete frame, if any. Bytes that cannot be parsed are held back until `flush`,
+    /// in case more of them complete a frame.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        let start = self
+            .buffer
+            .iter()
+            .position(|byte| !byte.is_ascii_whitespace())?;
+
+        self.buffer.advance(start);
+
+        let mut cursor = Cursor::new(&self.buffer[..]);
+
+        let frame = if self.buffer[0] == b'{' {
+            JsonResponse.read(&mut cursor).map(Frame::Json)
+        } else {
+            LinesResponse::new(1)
+                .read(&mut cursor)
+                .map(|mut lines| Frame::Line(lines.remove(0)))
+        };
+
+        let consumed = cursor.position() as usize;
+
+        match frame {
+            Ok(frame) => {
+                self.buffer.advance(consumed);
+                Some(frame)
+            }
+
+            Err(Error::Incomplete) => None,
+
+            // Garbled: handed out as it is, rather than holding up what comes after it.
+            Err(_) => Some(self.flush()?),
+        }
+    }
+
+    /// Hands out whatever is held back, as a raw frame.
+    pub fn flush(&mut self) -> Option<Frame> {
+        (!self.buffer.is_empty()).then(|| Frame::Raw(self.buffer.split().to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn json(frame: Option<Frame>) -> Value {
+        match frame {
+            Some(Frame::Json(value)) => value,
+            frame => panic!("Expected JSON, got {:?}", frame),
+        }
+    }
+
+    #[test]
+    fn frame_split_across_pushes() {
+        let mut decoder = Decoder::default();
+
+        decoder.push(br#"{"ACK":"DON"#);
+        assert!(decoder.next_frame().is_none());
+
+        decoder.push(br#"GLE_OK"}"#);
+        assert_eq!(json(decoder.next_frame()), json!({ "ACK": "DONGLE_OK" }));
+        assert!(decoder.next_frame().is_none());
+    }
+
+    #[test]
+    fn values_pushed_together_are_split() {
+        let mut decoder = Decoder::default();
+
+        decoder.push(br#"{"CMD-DONGLE":"ALIVE"} {"CMD-RTS":["UP",3]}"#);
+
+        assert_eq!(json(decoder.next_frame()), json!({ "CMD-DONGLE": "ALIVE" }));
+        assert_eq!(json(decoder.next_frame()), json!({ "CMD-RTS": ["UP", 3] }));
+        assert!(decoder.next_frame().is_none());
+        assert!(decoder.flush().is_none());
+    }
+
+    #[test]
+    fn line_then_value() {
+        let mut decoder = Decoder::default();
+
+        decoder.push(b"RTSDONGLE,OK,1.0.0\r\n{\"ACK\":\"DONGLE_OK\"}");
+
+        assert!(
+            matches!(decoder.next_frame(), Some(Frame::Line(line)) if line == "RTSDONGLE,OK,1.0.0")
+        );
+        assert_eq!(json(decoder.next_frame()), json!({ "ACK": "DONGLE_OK" }));
+    }
+
+    #[test]
+    fn garbage_is_handed_out_raw() {
+        let mut decoder = Decoder::default();
+
+        decoder.push(br#"{"ACK":]"#);
+
+        assert!(matches!(decoder.next_frame(), Some(Frame::Raw(bytes)) if bytes == br#"{"ACK":]"#));
+        assert!(decoder.next_frame().is_none());
+    }
+
+    #[test]
+    fn unterminated_message_waits_for_flush() {
+        let mut decoder = Decoder::default();
+
+        decoder.push(b"$CRYPTO_OFF_3145");
+
+        assert!(decoder.next_frame().is_none());
+        assert!(matches!(decoder.flush(), Some(Frame::Raw(bytes)) if bytes == b"$CRYPTO_OFF_3145"));
+        assert!(decoder.flush().is_none());
+    }
+}
@@ -1,14 +1,18 @@
 use std::str::FromStr;
 
 use log::trace;
-use tokio_serial::{DataBits, SerialPortInfo, SerialPortType, StopBits, UsbPortInfo};
+use tokio_serial::{DataBits, SerialPortInfo, SerialPortType, SerialStream, StopBits, UsbPortInfo};
 
 use self::api::somfy_dongle;
 
 pub use api::model::*;
-pub use api::{ SomfyRTSDongle, Waiting, Ready, WireFormat};
+pub use api::{ SomfyRTSDongle, Waiting, Ready, WireFormat, Observer, Error};
 
 mod api;
+pub mod frame;
+pub mod rpc;
+pub mod scene;
+pub mod transport;
 
 pub fn detect() -> Vec<SerialPortInfo> {
     tokio_serial::available_ports()
@@ -29,12 +33,22 @@ pub fn detect() -> Vec<SerialPortInfo> {
         .collect()
 }
 
+/// Talks to the dongle through something else than its serial port, such as a `Replay`.
+pub fn from_transport(transport: impl transport::Transport + 'static) -> SomfyRTSDongle<Waiting> {
+    somfy_dongle(transport)
+}
+
 pub fn new(path: &str) -> Result<SomfyRTSDongle<Waiting>, tokio_serial::Error> {
+    open_port(path).map(somfy_dongle)
+}
+
+/// Opens a serial port with the dongle's settings, to talk the dongle's protocol on it.
+pub fn open_port(path: &str) -> Result<SerialStream, tokio_serial::Error> {
     let serial_port = tokio_serial::new(path, 9600)
         .data_bits(DataBits::Eight)
         .stop_bits(StopBits::One);
 
-    tokio_serial::SerialStream::open(&serial_port).map(somfy_dongle)
+    SerialStream::open(&serial_port)
 }
 
 impl<T> From<Response<T>> for Result<T, String> {
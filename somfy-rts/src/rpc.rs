@@ -0,0 +1,142 @@
+//! Calls to the process owning the dongle, such as `somfy-rts-mqtt`, over a Unix socket, so
+//! that other tools need not open the serial port it holds. Each request and each answer is
+//! a JSON object on a line of its own.
+
+use std::{env, path::PathBuf};
+#[cfg(unix)]
+use std::{io, path::Path};
+
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+        UnixStream,
+    },
+};
+
+#[cfg(unix)]
+use super::Error;
+use super::{scene::Move, AddressVal};
+
+/// Name of the socket in the runtime directory.
+const SOCKET_NAME: &str = "somfy-rts.sock";
+
+/// Runtime directory of the system service, which has no `$XDG_RUNTIME_DIR`.
+const SERVICE_DIR: &str = "/run/somfy-rts";
+
+/// Where the owner of the dongle listens, unless told otherwise: in the user's runtime
+/// directory (`$XDG_RUNTIME_DIR`), or in `/run/somfy-rts` for a system service.
+pub fn default_socket() -> PathBuf {
+    default_sockets().remove(0)
+}
+
+/// Where the owner of the dongle may listen, unless told otherwise, most likely first: a
+/// user's shell has a runtime directory, which the system service it talks to lacks.
+pub fn default_sockets() -> Vec<PathBuf> {
+    let service = PathBuf::from(SERVICE_DIR).join(SOCKET_NAME);
+
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => vec![PathBuf::from(dir).join(SOCKET_NAME), service],
+        _ => vec![service],
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    /// Serial number of the dongle, needed when the owner drives several of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dongle: Option<String>,
+
+    #[serde(flatten)]
+    pub call: Call,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "call", rename_all = "kebab-case")]
+pub enum Call {
+    /// Serial number and firmware of the dongle.
+    Info,
+
+    Operate {
+        blind: u8,
+        order: Move,
+    },
+
+    /// Pairs a blind in programming mode with a slot.
+    Prog {
+        blind: u8,
+    },
+
+    GetAddress {
+        blind: u8,
+    },
+
+    ResetAddress {
+        blind: u8,
+    },
+
+    /// Runs a scene, as configured on the owner's side.
+    RunScene {
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+pub enum Answer {
+    Done,
+
+    Info {
+        serial: String,
+        firmware: String,
+    },
+
+    Blind(AddressVal),
+
+    /// The dongle answered with `DONGLE_KO`.
+    Refused {
+        message: String,
+    },
+
+    /// The call could not go through.
+    Error {
+        message: String,
+    },
+}
+
+/// A connection to the owner of the dongle.
+#[cfg(unix)]
+pub struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+#[cfg(unix)]
+impl Client {
+    pub async fn connect(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let (reader, writer) = UnixStream::connect(path).await?.into_split();
+
+        Ok(Client {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        })
+    }
+
+    /// Sends a request, then waits for its answer.
+    pub async fn call(&mut self, request: &Request) -> Result<Answer, Error> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+
+        self.writer.write_all(line.as_bytes()).await?;
+
+        let answer = self
+            .lines
+            .next_line()
+            .await?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+
+        Ok(serde_json::from_str(&answer)?)
+    }
+}
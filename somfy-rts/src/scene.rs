@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{Error, RtsCommand};
+
+/// Orders a scene can send.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Move {
+    #[serde(alias = "UP")]
+    Up,
+
+    #[serde(alias = "DOWN")]
+    Down,
+
+    #[serde(alias = "STOP")]
+    Stop,
+
+    #[serde(alias = "MY")]
+    My,
+}
+
+/// What running a scene comes down to.
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    Send(RtsCommand),
+    Wait(Duration),
+}
+
+#[derive(Error, Debug)]
+pub enum SceneError {
+    #[error("{:?} refused by dongle: {}", .0, .1)]
+    Refused(RtsCommand, String),
+
+    #[error(transparent)]
+    Dongle(#[from] Error),
+}
+
+impl Move {
+    pub fn rts(&self, id: u8) -> RtsCommand {
+        match self {
+            Self::Up => RtsCommand::Up(id),
+            Self::Down => RtsCommand::Down(id),
+            Self::Stop => RtsCommand::Stop(id),
+            Self::My => RtsCommand::My(id),
+        }
+    }
+}
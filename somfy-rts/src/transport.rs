@@ -0,0 +1,308 @@
+//! What the dongle is talked to through: its serial port, a recording of the traffic going
+//! through it, or the replay of such a recording.
+//!
+//! Captures hold a JSON object per line: when the bytes went through (`time_ms`, since the
+//! epoch), which way (`tx` to the dongle, `rx` from it), and the bytes themselves, in hex.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, LineWriter, Write},
+    mem,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::mpsc,
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Direction {
+    Tx,
+    Rx,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    time_ms: u64,
+    dir: Direction,
+    #[serde(with = "hex")]
+    data: Vec<u8>,
+}
+
+/// Tees every byte written to and read from a transport into a capture file.
+///
+/// The file is written by a thread of its own, for reads and writes not to wait on the disk.
+/// Dropping the recorder waits for the capture to be complete.
+pub struct Recorder<T> {
+    inner: T,
+    capture: mpsc::Sender<Entry>,
+    writer: Option<JoinHandle<()>>,
+}
+
+/// Plays a capture back: bytes written must be the ones recorded, and reads get the dongle's
+/// recorded answers, as soon as what came before them was written.
+pub struct Replay {
+    entries: VecDeque<(Direction, Vec<u8>)>,
+    waker: Option<Waker>,
+}
+
+/// Name of a new capture for the dongle at `port`, in directory `dir`.
+pub fn capture_file(dir: &Path, port: &str) -> PathBuf {
+    let port = Path::new(port)
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+
+    dir.join(format!("{}-{}.capture", port, now_ms()))
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<T: Transport> Recorder<T> {
+    /// Records the traffic of `inner` to `path`, creating the directories leading to it.
+    pub fn new(inner: T, path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut capture = LineWriter::new(File::create(path)?);
+
+        let (sender, entries) = mpsc::channel::<Entry>();
+
+        let writer = thread::Builder::new()
+            .name("somfy-rts-record".to_string())
+            .spawn(move || {
+                for entry in entries {
+                    // A capture missing a few lines is still of use: the dongle must go on
+                    // regardless.
+                    if let Err(e) = serde_json::to_writer(&mut capture, &entry)
+                        .map_err(io::Error::from)
+                        .and_then(|_| capture.write_all(b"\n"))
+                    {
+                        warn!(target:"libsomfy_rts::record", "Failed to record traffic: {}", e);
+                    }
+                }
+            })?;
+
+        Ok(Recorder {
+            inner,
+            capture: sender,
+            writer: Some(writer),
+        })
+    }
+
+    fn record(&mut self, dir: Direction, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        let entry = Entry {
+            time_ms: now_ms(),
+            dir,
+            data: data.to_vec(),
+        };
+
+        // The writing thread only stops once the recorder is dropped.
+        let _ = self.capture.send(entry);
+    }
+}
+
+impl<T> Drop for Recorder<T> {
+    fn drop(&mut self) {
+        // The writing thread stops once every sender is gone.
+        drop(mem::replace(&mut self.capture, mpsc::channel().0));
+
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl<T: Transport> AsyncRead for Recorder<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = poll {
+            this.record(Direction::Rx, &buf.filled()[filled..]);
+        }
+
+        poll
+    }
+}
+
+impl<T: Transport> AsyncWrite for Recorder<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(written)) = poll {
+            this.record(Direction::Tx, &buf[..written]);
+        }
+
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut entries = VecDeque::<(Direction, Vec<u8>)>::new();
+
+        for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry = serde_json::from_str::<Entry>(&line)
+                .map_err(|e| invalid_data(format!("Line {} of capture: {}", number + 1, e)))?;
+
+            // Chunks going the same way are merged, as they may be split differently on replay.
+            match entries.back_mut() {
+                Some((dir, data)) if *dir == entry.dir => data.extend(entry.data),
+                _ => entries.push_back((entry.dir, entry.data)),
+            }
+        }
+
+        Ok(Replay {
+            entries,
+            waker: None,
+        })
+    }
+
+    /// Whether everything recorded was played back.
+    pub fn is_done(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl AsyncRead for Replay {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        match this.entries.front_mut() {
+            // The end of the capture reads as the dongle going away.
+            None => Poll::Ready(Ok(())),
+
+            Some((Direction::Tx, _)) => {
+                this.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+
+            Some((Direction::Rx, data)) => {
+                let len = data.len().min(buf.remaining());
+
+                buf.put_slice(&data[..len]);
+                data.drain(..len);
+
+                if data.is_empty() {
+                    this.entries.pop_front();
+                }
+
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
+
+impl AsyncWrite for Replay {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        let expected = match this.entries.front_mut() {
+            Some((Direction::Tx, expected)) => expected,
+
+            Some((Direction::Rx, _)) => {
+                return Poll::Ready(Err(invalid_data(format!(
+                    "Capture has the dongle answer before {} is sent",
+                    String::from_utf8_lossy(buf)
+                ))))
+            }
+
+            None => {
+                return Poll::Ready(Err(invalid_data(format!(
+                    "Capture ended before {} was sent",
+                    String::from_utf8_lossy(buf)
+                ))))
+            }
+        };
+
+        let len = expected.len().min(buf.len());
+
+        if expected[..len] != buf[..len] {
+            return Poll::Ready(Err(invalid_data(format!(
+                "Capture expected {}, got {}",
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(buf)
+            ))));
+        }
+
+        expected.drain(..len);
+
+        if expected.is_empty() {
+            this.entries.pop_front();
+
+            if let Some(waker) = this.waker.take() {
+                waker.wake();
+            }
+        }
+
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
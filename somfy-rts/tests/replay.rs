@@ -0,0 +1,94 @@
+use std::{
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use somfy_rts::{transport::Replay, Response, RtsCommand, WireFormat};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Hands the replay over to the dongle, while keeping a hold on it to tell whether it was
+/// played through.
+#[derive(Clone)]
+struct Shared(Arc<Mutex<Replay>>);
+
+impl AsyncRead for Shared {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Shared {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_shutdown(cx)
+    }
+}
+
+/// A capture of `somfy-rts-cli up 3`, recorded against the simulator.
+fn capture() -> Shared {
+    let path: PathBuf = [
+        env!("CARGO_MANIFEST_DIR"),
+        "tests",
+        "fixtures",
+        "up.capture",
+    ]
+    .iter()
+    .collect();
+
+    Shared(Arc::new(Mutex::new(Replay::open(&path).unwrap())))
+}
+
+#[tokio::test]
+async fn replays_a_recorded_session() {
+    let replay = capture();
+
+    let (firmware, mut dongle) = somfy_rts::from_transport(replay.clone())
+        .initialize(WireFormat::CryptoOff)
+        .await
+        .unwrap();
+
+    assert_eq!(firmware, "1.0.0");
+
+    match dongle.test_alive().await.unwrap() {
+        Response::DongleOk(alive) => assert_eq!(alive.id[0], "SIM0000001"),
+        Response::Err(e) => panic!("Dongle refused ALIVE: {}", e),
+    }
+
+    match dongle.operate_blind(RtsCommand::Up(3)).await.unwrap() {
+        Response::DongleOk(blind) => assert_eq!(blind.address_val[1], "1A0003"),
+        Response::Err(e) => panic!("Dongle refused UP: {}", e),
+    }
+
+    assert!(replay.0.lock().unwrap().is_done());
+}
+
+#[tokio::test]
+async fn replay_fails_on_unrecorded_command() {
+    let replay = capture();
+
+    let (_, mut dongle) = somfy_rts::from_transport(replay.clone())
+        .initialize(WireFormat::CryptoOff)
+        .await
+        .unwrap();
+
+    assert!(dongle.operate_blind(RtsCommand::Down(3)).await.is_err());
+    assert!(!replay.0.lock().unwrap().is_done());
+}